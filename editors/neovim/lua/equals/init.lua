@@ -3,13 +3,9 @@ local M = {}
 local default_config = {
   cmd = "equals",
   extra_args = {},
-  language_map = {
-    python = "python",
-    python3 = "python",
-    numbat = "numbat",
-    nb = "numbat",
-    nbt = "numbat",
-  },
+  -- Extra filetype -> language entries; names, aliases and fence tags come
+  -- from `equals languages`.
+  language_map = {},
   pass_filetype = false,
   markdown_filetypes = {
     markdown = true,
@@ -113,20 +109,6 @@ local function is_markdown(ft, override)
   return set[ft] or false
 end
 
-local function resolve_language(ft, override)
-  if type(override) == "string" and override ~= "" then
-    return override
-  end
-
-  if ft and ft ~= "" then
-    if config.language_map[ft] then
-      return config.language_map[ft]
-    elseif config.pass_filetype then
-      return ft
-    end
-  end
-end
-
 local function cleanup(paths)
   for _, path in ipairs(paths) do
     if path and #path > 0 then
@@ -154,6 +136,57 @@ local function run_cli(cmd)
   }
 end
 
+local known_languages_cache = nil
+
+-- Maps every name, alias and fence tag reported by `equals languages` to
+-- its language, so built-in, config and plugin languages need no entry in
+-- `language_map`.
+local function known_languages()
+  if known_languages_cache then
+    return known_languages_cache
+  end
+
+  known_languages_cache = {}
+  local ok, result = pcall(run_cli, { config.cmd, "languages" })
+  if not ok or result.code ~= 0 then
+    return known_languages_cache
+  end
+
+  -- Lines look like `python (built-in); aliases: python3, py; extensions: py`.
+  for line in result.stdout:gmatch("[^\n]+") do
+    local name = line:match("^(%S+) %(")
+    if name then
+      known_languages_cache[name] = name
+      for label, items in line:gmatch(";%s*(%a+): ([^;]+)") do
+        if label == "aliases" or label == "fences" then
+          for item in items:gmatch("[^,%s]+") do
+            if known_languages_cache[item] == nil then
+              known_languages_cache[item] = name
+            end
+          end
+        end
+      end
+    end
+  end
+  return known_languages_cache
+end
+
+local function resolve_language(ft, override)
+  if type(override) == "string" and override ~= "" then
+    return override
+  end
+
+  if ft and ft ~= "" then
+    if config.language_map[ft] then
+      return config.language_map[ft]
+    elseif known_languages()[ft] then
+      return known_languages()[ft]
+    elseif config.pass_filetype then
+      return ft
+    end
+  end
+end
+
 local function apply_highlight(bufnr)
   if not config.highlight.enable then
     return
@@ -348,6 +381,7 @@ end
 
 function M.setup(opts)
   config = vim.tbl_deep_extend("force", config, opts or {})
+  known_languages_cache = nil
   refresh_cached_tables()
   configure_highlights()
 end
@@ -0,0 +1,260 @@
+use crate::{
+    document::{Block, Document, Line, Session},
+    lang::registry::Registry,
+    parser::Parser,
+};
+
+/// Parses AsciiDoc documents: `[source,lang]` listing blocks delimited by
+/// `----`, plus inline monospace spans (`` `2 + 2 #=` `` and the literal
+/// form `` `+2 + 2 #=+` ``).
+///
+/// A `[source]` block without a language uses the `:source-language:`
+/// document attribute, falling back to the document language. Listing
+/// blocks without a `source` style and blocks in unknown languages stay text.
+pub struct AsciiDocParser {
+    registry: Registry,
+}
+
+/// Where the parser is between lines.
+enum State {
+    Text,
+    /// Saw a `[source,...]` line; waiting for the opening delimiter.
+    /// `None` means the block won't be evaluated.
+    Attribute(Option<Session>),
+    /// Inside a listing block closed by `delimiter`.
+    Listing {
+        delimiter: String,
+        session: Option<Session>,
+    },
+}
+
+impl AsciiDocParser {
+    pub fn new() -> Self {
+        Self {
+            registry: Registry::default(),
+        }
+    }
+
+    /// Reads `[source,lang]` and `:source-language:` names from `registry`.
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    /// Recognises `[source,python]`, `[source, numbat, linenums]` and the
+    /// `[,python]` shorthand. Returns the language, if one is given.
+    fn source_attribute(line: &str) -> Option<Option<&str>> {
+        let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+        let mut parts = inner.split(',').map(str::trim);
+        let style = parts.next()?;
+        let language = parts.next().filter(|l| !l.is_empty() && !l.contains('='));
+        match style {
+            "source" => Some(language),
+            "" if language.is_some() => Some(language),
+            _ => None,
+        }
+    }
+
+    fn is_listing_delimiter(line: &str) -> bool {
+        let trimmed = line.trim_end();
+        trimmed.len() >= 4 && trimmed.chars().all(|c| c == '-')
+    }
+
+    fn is_block_title(line: &str) -> bool {
+        line.starts_with('.') && !line.starts_with("..") && line.len() > 1
+    }
+
+    /// Splits out monospace spans, using the content between `+` signs for
+    /// literal spans so the passthrough markers stay text.
+    fn parse_inline_line(line: &str) -> Line {
+        let mut blocks = Vec::new();
+        let mut text_start = 0;
+        let mut search_from = 0;
+
+        while let Some(open) = line[search_from..].find('`').map(|p| p + search_from) {
+            let Some(close) = line[open + 1..].find('`').map(|p| p + open + 1) else {
+                break;
+            };
+            search_from = close + 1;
+
+            let mut code = open + 1..close;
+            let inner = &line[code.clone()];
+            if inner.len() >= 2 && inner.starts_with('+') && inner.ends_with('+') {
+                code = code.start + 1..code.end - 1;
+            }
+            if code.is_empty() {
+                continue;
+            }
+
+            blocks.push(Block::Text(
+                (text_start, code.start),
+                line[text_start..code.start].to_string(),
+            ));
+            blocks.push(Block::Code(
+                (code.start, code.end),
+                line[code.clone()].to_string(),
+                Session::default(),
+            ));
+            text_start = code.end;
+        }
+
+        if text_start < line.len() || blocks.is_empty() {
+            blocks.push(Block::Text(
+                (text_start, line.len()),
+                line[text_start..].to_string(),
+            ));
+        }
+        Line { blocks }
+    }
+}
+
+impl Parser for AsciiDocParser {
+    fn parse(&self, input: &str) -> Document {
+        let mut lines = Vec::new();
+        let mut state = State::Text;
+        let mut default_language: Option<&str> = None;
+
+        for line in input.lines() {
+            state = match state {
+                State::Listing { delimiter, session } => {
+                    if line.trim_end() == delimiter {
+                        lines.push(Line::text(line));
+                        State::Text
+                    } else {
+                        lines.push(match &session {
+                            Some(session) => Line {
+                                blocks: vec![Block::Code(
+                                    (0, line.len()),
+                                    line.to_string(),
+                                    session.clone(),
+                                )],
+                            },
+                            None => Line::text(line),
+                        });
+                        State::Listing { delimiter, session }
+                    }
+                }
+                State::Attribute(session) if Self::is_listing_delimiter(line) => {
+                    lines.push(Line::text(line));
+                    State::Listing {
+                        delimiter: line.trim_end().to_string(),
+                        session,
+                    }
+                }
+                State::Attribute(session) if Self::is_block_title(line) => {
+                    lines.push(Line::text(line));
+                    State::Attribute(session)
+                }
+                State::Attribute(_) | State::Text => {
+                    if let Some(language) = line
+                        .strip_prefix(":source-language:")
+                        .and_then(|l| self.registry.for_fence(l.trim()))
+                    {
+                        default_language = Some(language);
+                    }
+
+                    if let Some(language) = Self::source_attribute(line) {
+                        let session = match language {
+                            Some(language) => self
+                                .registry
+                                .for_fence(language)
+                                .map(|l| Session::default().with_language(l)),
+                            None => Some(match default_language {
+                                Some(l) => Session::default().with_language(l),
+                                None => Session::default(),
+                            }),
+                        };
+                        lines.push(Line::text(line));
+                        State::Attribute(session)
+                    } else if Self::is_listing_delimiter(line) {
+                        // A plain listing block, e.g. shell output.
+                        lines.push(Line::text(line));
+                        State::Listing {
+                            delimiter: line.trim_end().to_string(),
+                            session: None,
+                        }
+                    } else {
+                        lines.push(Self::parse_inline_line(line));
+                        State::Text
+                    }
+                }
+            };
+        }
+
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_block_takes_language_from_attribute() {
+        let src = "\
+= Manual
+
+[source,numbat]
+.Unit conversion
+----
+let length = 3 ft
+length -> cm #=
+----";
+        let doc = AsciiDocParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("let length = 3 ft", Some("numbat")),
+                ("length -> cm #=", Some("numbat")),
+            ]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn plain_and_unknown_listings_stay_text() {
+        let src = "\
+----
+$ equals --help
+----
+
+[source,ruby]
+----
+puts 1
+----";
+        let doc = AsciiDocParser::new().parse(src);
+        assert!(doc.code_blocks().is_empty());
+    }
+
+    #[test]
+    fn source_language_attribute_sets_block_default() {
+        let src = ":source-language: fend\n\n[source]\n----\n1 mile in km #=\n----\n\n[,python]\n----\n2 ** 3 #=\n----";
+        let doc = AsciiDocParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("1 mile in km #=", Some("fend")),
+                ("2 ** 3 #=", Some("python"))
+            ]
+        );
+    }
+
+    #[test]
+    fn monospace_and_literal_spans_are_code() {
+        let src = "Area is `+3 * 4 #=+` and `2 + 2 #=`, not `` here.";
+        let doc = AsciiDocParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("3 * 4 #=", None), ("2 + 2 #=", None)]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+}
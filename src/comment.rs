@@ -0,0 +1,380 @@
+use std::ops::Range;
+
+use crate::{
+    config::Config,
+    document::{Block, Document, Line, Session},
+    parser::Parser,
+};
+
+/// Comment and string tokens of a host language.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommentSyntax {
+    /// Tokens that comment out the rest of the line, e.g. `//`.
+    pub line: Vec<String>,
+    /// Opening and closing tokens of block comments, e.g. `/*` and `*/`.
+    /// Listed longest first where one opener starts with another token.
+    pub block: Vec<(String, String)>,
+    /// Quote characters of string literals, so `"// not a comment"` is skipped.
+    pub strings: Vec<char>,
+}
+
+impl CommentSyntax {
+    fn new(line: &[&str], block: &[(&str, &str)], strings: &[char]) -> Self {
+        Self {
+            line: line.iter().map(|token| token.to_string()).collect(),
+            block: block
+                .iter()
+                .map(|(open, close)| (open.to_string(), close.to_string()))
+                .collect(),
+            strings: strings.to_vec(),
+        }
+    }
+
+    /// The comment syntax for a source file extension (lowercase): a
+    /// `[comments]` table of the config, or a built-in one.
+    pub fn for_extension(ext: &str, config: &Config) -> Option<Self> {
+        if let Some(spec) = config
+            .comments
+            .iter()
+            .find(|spec| spec.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+        {
+            return Some(Self {
+                line: spec.line.clone(),
+                block: spec.block.clone(),
+                strings: spec.strings.clone(),
+            });
+        }
+        Some(match ext {
+            "rs" | "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "java" | "js" | "mjs" | "ts"
+            | "tsx" | "jsx" | "go" | "swift" | "kt" | "kts" | "cs" | "scala" | "dart" | "zig"
+            | "css" | "scss" => Self::new(&["//"], &[("/*", "*/")], &['"']),
+            "sh" | "bash" | "zsh" | "rb" | "pl" | "r" | "toml" | "yaml" | "yml" | "ini"
+            | "conf" | "cmake" | "mk" => Self::new(&["#"], &[], &['"', '\'']),
+            "sql" => Self::new(&["--"], &[("/*", "*/")], &['\'']),
+            "lua" => Self::new(&["--"], &[("--[[", "]]")], &['"', '\'']),
+            "hs" | "elm" => Self::new(&["--"], &[("{-", "-}")], &['"']),
+            "el" | "clj" | "cljs" | "lisp" | "scm" => Self::new(&[";"], &[], &['"']),
+            "html" | "htm" | "xml" | "svg" => Self::new(&[], &[("<!--", "-->")], &[]),
+            _ => return None,
+        })
+    }
+}
+
+/// Parses source files of another language, evaluating only the text inside
+/// comments: `// 1920*1080*4 bytes -> MiB #=` in Rust, `-- ...` in SQL.
+///
+/// Only comments holding the eval marker are code, so prose comments around
+/// them aren't run. Between `equals: begin` and `equals: end` comments, every
+/// comment is code, for definitions that later markers use:
+///
+/// ```text
+/// // equals: begin
+/// // let frame = 1920*1080*4 bytes
+/// // equals: end
+/// ```
+///
+/// Comment tokens and the decoration that follows them (`///`, ` * ` in
+/// block comments) stay text, as does all the surrounding code.
+pub struct CommentParser {
+    syntax: CommentSyntax,
+    marker: String,
+}
+
+const BEGIN_CALC: &str = "equals: begin";
+const END_CALC: &str = "equals: end";
+
+/// Tracks what is open at the end of a line.
+#[derive(Default)]
+struct ParseState {
+    /// The unclosed block comment, by index into `CommentSyntax::block`.
+    open_block: Option<usize>,
+    /// Inside an `equals: begin` region.
+    in_calc: bool,
+}
+
+impl CommentParser {
+    pub fn new(syntax: CommentSyntax, marker: impl Into<String>) -> Self {
+        Self {
+            syntax,
+            marker: marker.into(),
+        }
+    }
+
+    /// Splits `line` into code and text, carrying an unclosed block comment
+    /// over to the next line.
+    fn parse_line(&self, line: &str, state: &mut ParseState) -> Line {
+        let mut comments = Vec::new();
+        let mut quote: Option<char> = None;
+        let mut pos = 0;
+
+        if state.open_block.is_some() {
+            pos = Self::skip_continuation(line);
+        }
+
+        while pos < line.len() {
+            let rest = &line[pos..];
+
+            if let Some(open) = state.open_block {
+                let close = &self.syntax.block[open].1;
+                let end = rest.find(close.as_str()).map(|p| pos + p);
+                comments.push(Self::content(line, pos..end.unwrap_or(line.len())));
+                match end {
+                    Some(end) => {
+                        state.open_block = None;
+                        pos = end + close.len();
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+
+            let Some(ch) = rest.chars().next() else {
+                break;
+            };
+            if let Some(q) = quote {
+                if ch == '\\' {
+                    pos += 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+                    continue;
+                }
+                if ch == q {
+                    quote = None;
+                }
+                pos += ch.len_utf8();
+                continue;
+            }
+
+            if self.syntax.strings.contains(&ch) {
+                quote = Some(ch);
+                pos += 1;
+            } else if let Some(open) = self
+                .syntax
+                .block
+                .iter()
+                .position(|(open, _)| rest.starts_with(open.as_str()))
+            {
+                let token = &self.syntax.block[open].0;
+                state.open_block = Some(open);
+                pos += token.len();
+                pos += Self::decoration_len(&line[pos..], token);
+            } else if let Some(token) = self
+                .syntax
+                .line
+                .iter()
+                .find(|t| rest.starts_with(t.as_str()))
+            {
+                let start = pos + token.len();
+                let start = start + Self::decoration_len(&line[start..], token);
+                comments.push(Self::content(line, start..line.len()));
+                break;
+            } else {
+                pos += ch.len_utf8();
+            }
+        }
+
+        self.build_line(line, &comments, state)
+    }
+
+    /// Length of the decoration after a comment token: repeats of its
+    /// characters (`///`, `/**`, `---`) and a doc-comment `!`.
+    fn decoration_len(rest: &str, token: &str) -> usize {
+        rest.len().saturating_sub(
+            rest.trim_start_matches(|c| token.contains(c) || c == '!')
+                .len(),
+        )
+    }
+
+    /// Skips the leading ` * ` of a line inside a block comment.
+    fn skip_continuation(line: &str) -> usize {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('*') && !trimmed.starts_with("*/") {
+            line.len() - trimmed.trim_start_matches('*').len()
+        } else {
+            0
+        }
+    }
+
+    /// Trims whitespace off a comment's text so only the expression is code.
+    fn content(line: &str, range: Range<usize>) -> Range<usize> {
+        let text = &line[range.clone()];
+        let start = range.start + (text.len() - text.trim_start().len());
+        let end = range.end - (text.len() - text.trim_end().len());
+        start..end.max(start)
+    }
+
+    /// Whether a comment's text is evaluated, opening or closing a calc
+    /// region when it is a directive.
+    fn is_code(&self, comment: &str, state: &mut ParseState) -> bool {
+        match comment {
+            BEGIN_CALC => state.in_calc = true,
+            END_CALC => state.in_calc = false,
+            _ => return state.in_calc || comment.contains(self.marker.as_str()),
+        }
+        false
+    }
+
+    fn build_line(&self, line: &str, comments: &[Range<usize>], state: &mut ParseState) -> Line {
+        let mut blocks = Vec::new();
+        let mut text_start = 0;
+
+        for range in comments.iter().filter(|r| !r.is_empty()) {
+            if !self.is_code(&line[range.clone()], state) {
+                continue;
+            }
+            if range.start > text_start {
+                blocks.push(Block::Text(
+                    (text_start, range.start),
+                    line[text_start..range.start].to_string(),
+                ));
+            }
+            blocks.push(Block::Code(
+                (range.start, range.end),
+                line[range.clone()].to_string(),
+                Session::default(),
+            ));
+            text_start = range.end;
+        }
+
+        if blocks.is_empty() {
+            return Line::text(line);
+        }
+        if text_start < line.len() {
+            blocks.push(Block::Text(
+                (text_start, line.len()),
+                line[text_start..].to_string(),
+            ));
+        }
+        Line { blocks }
+    }
+}
+
+impl Parser for CommentParser {
+    fn parse(&self, input: &str) -> Document {
+        let mut lines = Vec::new();
+        let mut state = ParseState::default();
+
+        for (i, line) in input.lines().enumerate() {
+            if i == 0 && line.starts_with("#!") {
+                lines.push(Line::text(line));
+                continue;
+            }
+            lines.push(self.parse_line(line, &mut state));
+        }
+
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    fn parser(ext: &str) -> CommentParser {
+        let syntax = CommentSyntax::for_extension(ext, &Config::default()).unwrap();
+        CommentParser::new(syntax, "#=")
+    }
+
+    #[test]
+    fn only_line_comment_text_is_code() {
+        let src = "\
+fn main() {
+    let url = \"http://example.com\"; // 1920*1080*4 bytes -> MiB #=
+    /// 2 ** 10 #=
+    println!(\"{url}\");
+}";
+        let doc = parser("rs").parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("1920*1080*4 bytes -> MiB #=", None), ("2 ** 10 #=", None)]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn block_comments_span_lines() {
+        let src = "\
+int x = 1; /* 3 m -> ft #= */ int y = 2;
+/**
+ * equals: begin
+ * let r = 2 m
+ * equals: end
+ * r * 3 #=
+ */";
+        let doc = parser("rs").parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("3 m -> ft #=", None),
+                ("let r = 2 m", None),
+                ("r * 3 #=", None)
+            ]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn comment_tokens_inside_strings_are_ignored() {
+        let src = "SELECT '-- not a comment', 'it''s' -- 1 GB -> MB #=";
+        let doc = parser("sql").parse(src);
+        assert_eq!(doc.code_blocks(), vec![("1 GB -> MB #=", None)]);
+    }
+
+    #[test]
+    fn shebang_and_empty_comments_stay_text() {
+        let src = "#!/bin/sh\n#\necho hi # 2 + 2 #=";
+        let doc = parser("sh").parse(src);
+        assert_eq!(doc.code_blocks(), vec![("2 + 2 #=", None)]);
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn prose_comments_stay_text() {
+        let src = "\
+// Returns the frame size
+// 1920*1080*4 #=
+fn frame() {} // equals: begin
+// let w = 1920
+/* w * 2 */
+// equals: end
+// w #=";
+        let doc = parser("rs").parse(src);
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("1920*1080*4 #=", None),
+                ("let w = 1920", None),
+                ("w * 2", None),
+                ("w #=", None)
+            ]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn extensions_map_to_syntax() {
+        let config = Config::default();
+        assert_eq!(
+            CommentSyntax::for_extension("rs", &config).map(|s| s.line),
+            Some(vec!["//".to_string()])
+        );
+        assert_eq!(
+            CommentSyntax::for_extension("lua", &config).map(|s| s.block),
+            Some(vec![("--[[".to_string(), "]]".to_string())])
+        );
+        assert_eq!(CommentSyntax::for_extension("py", &config), None);
+
+        let config =
+            parse_config("[comments.proto]\nextensions = [\"proto\", \"rs\"]\nline = [\"//\"]")
+                .unwrap();
+        let proto = CommentSyntax::for_extension("PROTO", &config).unwrap();
+        assert!(proto.block.is_empty());
+        assert_eq!(CommentSyntax::for_extension("rs", &config), Some(proto));
+    }
+}
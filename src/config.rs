@@ -0,0 +1,542 @@
+//! Reads `equals.toml`, which defines extra languages by how to run a
+//! script and print a value:
+//!
+//! ```toml
+//! [languages.node]
+//! command = "node"
+//! args = ["-"]
+//! comment = "//"
+//! marker = "//="
+//! print = 'console.log("{sentinel}", {expr})'
+//! assignment = '^(?:const|let|var)\s+(\w+)\s*='
+//! strings = ['"', "'", "`"]
+//! aliases = ["js"]
+//! extensions = ["js", "mjs"]
+//! ```
+//!
+//! Language plugins (see `lang::plugin`) can be declared in `[plugins]`
+//! tables, with a `path` relative to the config file and the comment token
+//! and names the plugin's language uses. Without a `path`, the plugin is
+//! looked up on the search path as usual:
+//!
+//! ```toml
+//! [plugins.dsl]
+//! path = "tools/dsl-equals"
+//! comment = "--"
+//! marker = "--="
+//! extensions = ["dsl"]
+//! ```
+//!
+//! Comment mode reads the comments of more file types from `[comments]`
+//! tables; a block comment is its opening and closing token:
+//!
+//! ```toml
+//! [comments.proto]
+//! extensions = ["proto"]
+//! line = ["//"]
+//! block = ["/* */"]
+//! strings = ['"']
+//! ```
+//!
+//! Only the TOML needed for that is understood: `[a.b]` table headers,
+//! basic and literal strings, arrays of strings and `#` comments. Other
+//! tables and keys are ignored.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+pub const CONFIG_FILE: &str = "equals.toml";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub languages: Vec<GenericSpec>,
+    pub plugins: Vec<PluginSpec>,
+    pub comments: Vec<CommentSpec>,
+}
+
+/// A language defined in the config file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenericSpec {
+    pub name: String,
+    /// The interpreter, which reads the script on stdin.
+    pub command: String,
+    pub args: Vec<String>,
+    /// The line comment token, e.g. `//`.
+    pub comment: Option<String>,
+    pub marker: Option<String>,
+    /// Prints `{expr}` after `{sentinel}`, e.g. `print("{sentinel}", {expr})`.
+    pub print: String,
+    /// A regex whose first group is the variable an assignment defines.
+    pub assignment: Option<String>,
+    /// Quote characters of string literals, if not those of C.
+    pub strings: Option<Vec<char>>,
+    /// Other names, file extensions and fence tags that select the language.
+    pub aliases: Vec<String>,
+    pub extensions: Vec<String>,
+    pub fences: Vec<String>,
+}
+
+/// A language plugin declared in the config file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PluginSpec {
+    pub name: String,
+    /// The executable, instead of `equals-lang-<name>` on the search path.
+    pub path: Option<PathBuf>,
+    /// The line comment token, e.g. `--`.
+    pub comment: Option<String>,
+    pub marker: Option<String>,
+    /// Quote characters of string literals, if not those of C.
+    pub strings: Option<Vec<char>>,
+    /// Other names, file extensions and fence tags that select the language.
+    pub aliases: Vec<String>,
+    pub extensions: Vec<String>,
+    pub fences: Vec<String>,
+}
+
+/// The comment syntax of host files, for comment mode.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommentSpec {
+    pub extensions: Vec<String>,
+    pub line: Vec<String>,
+    /// Opening and closing tokens of block comments.
+    pub block: Vec<(String, String)>,
+    /// Quote characters of string literals.
+    pub strings: Vec<char>,
+}
+
+/// The table whose keys are being read.
+enum Section {
+    Language(GenericSpec),
+    Plugin(PluginSpec),
+    Comments(CommentSpec),
+    Other,
+}
+
+impl Config {
+    pub fn language(&self, name: &str) -> Option<&GenericSpec> {
+        self.languages.iter().find(|spec| spec.name == name)
+    }
+
+    pub fn plugin(&self, name: &str) -> Option<&PluginSpec> {
+        self.plugins.iter().find(|spec| spec.name == name)
+    }
+}
+
+/// Finds the nearest `equals.toml` in `dir` or its ancestors.
+pub fn find_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Loads the config nearest to `dir`; no file means an empty config.
+pub fn load_config(dir: &Path) -> Result<Config, String> {
+    let Some(path) = find_config(dir) else {
+        return Ok(Config::default());
+    };
+    let text = fs::read_to_string(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+    let mut config = parse_config(&text).map_err(|err| format!("{}: {err}", path.display()))?;
+    // Plugin paths are relative to the config file.
+    if let Some(dir) = path.parent() {
+        for plugin in &mut config.plugins {
+            if let Some(program) = &mut plugin.path {
+                *program = dir.join(&*program);
+            }
+        }
+    }
+    Ok(config)
+}
+
+pub fn parse_config(text: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+    let mut current = Section::Other;
+
+    for (idx, raw) in text.lines().enumerate() {
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {message}", idx + 1);
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| error("unclosed table header"))?
+                .trim();
+            config.close(current)?;
+            current = if let Some(name) = header.strip_prefix("languages.") {
+                Section::Language(GenericSpec {
+                    name: unquote_key(name).to_string(),
+                    ..GenericSpec::default()
+                })
+            } else if let Some(name) = header.strip_prefix("plugins.") {
+                Section::Plugin(PluginSpec {
+                    name: unquote_key(name).to_string(),
+                    ..PluginSpec::default()
+                })
+            } else if header.starts_with("comments.") {
+                Section::Comments(CommentSpec::default())
+            } else {
+                Section::Other
+            };
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected `key = value`"))?;
+        if matches!(current, Section::Other) {
+            continue;
+        }
+        let key = key.trim();
+        let value = Value::parse(value.trim()).map_err(|message| error(&message))?;
+        let spec = match &mut current {
+            Section::Language(spec) => spec,
+            Section::Plugin(spec) => {
+                read_plugin_key(spec, key, value).map_err(|message| error(&message))?;
+                continue;
+            }
+            Section::Comments(spec) => {
+                read_comment_key(spec, key, value).map_err(|message| error(&message))?;
+                continue;
+            }
+            Section::Other => continue,
+        };
+        match (key, value) {
+            ("command", Value::String(v)) => spec.command = v,
+            ("args", Value::Array(v)) => spec.args = v,
+            ("comment", Value::String(v)) => spec.comment = Some(v),
+            ("marker", Value::String(v)) => spec.marker = Some(v),
+            ("print", Value::String(v)) => spec.print = v,
+            ("assignment", Value::String(v)) => spec.assignment = Some(v),
+            ("strings", Value::Array(v)) => {
+                spec.strings = Some(quote_chars(&v).map_err(|message| error(&message))?);
+            }
+            ("aliases", Value::Array(v)) => spec.aliases = v,
+            ("extensions", Value::Array(v)) => spec.extensions = v,
+            ("fences", Value::Array(v)) => spec.fences = v,
+            (
+                key @ ("command" | "args" | "comment" | "marker" | "print" | "assignment"
+                | "strings" | "aliases" | "extensions" | "fences"),
+                _,
+            ) => {
+                return Err(error(&format!("`{key}` has the wrong type")));
+            }
+            _ => {}
+        }
+    }
+    config.close(current)?;
+    Ok(config)
+}
+
+impl Config {
+    /// Adds the table that was being read.
+    fn close(&mut self, section: Section) -> Result<(), String> {
+        match section {
+            Section::Language(spec) => self.languages.push(finish(spec)?),
+            Section::Plugin(spec) => self.plugins.push(spec),
+            Section::Comments(spec) => self.comments.push(spec),
+            Section::Other => {}
+        }
+        Ok(())
+    }
+}
+
+fn read_plugin_key(spec: &mut PluginSpec, key: &str, value: Value) -> Result<(), String> {
+    match (key, value) {
+        ("path", Value::String(v)) => spec.path = Some(PathBuf::from(v)),
+        ("comment", Value::String(v)) => spec.comment = Some(v),
+        ("marker", Value::String(v)) => spec.marker = Some(v),
+        ("strings", Value::Array(v)) => spec.strings = Some(quote_chars(&v)?),
+        ("aliases", Value::Array(v)) => spec.aliases = v,
+        ("extensions", Value::Array(v)) => spec.extensions = v,
+        ("fences", Value::Array(v)) => spec.fences = v,
+        (
+            key @ ("path" | "comment" | "marker" | "strings" | "aliases" | "extensions" | "fences"),
+            _,
+        ) => {
+            return Err(format!("`{key}` has the wrong type"));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn read_comment_key(spec: &mut CommentSpec, key: &str, value: Value) -> Result<(), String> {
+    let Value::Array(items) = value else {
+        return match key {
+            "extensions" | "line" | "block" | "strings" => {
+                Err(format!("`{key}` has the wrong type"))
+            }
+            _ => Ok(()),
+        };
+    };
+    match key {
+        "extensions" => spec.extensions = items,
+        "line" => spec.line = items,
+        "block" => {
+            spec.block = items
+                .iter()
+                .map(|item| {
+                    let (open, close) = item
+                        .split_once(' ')
+                        .ok_or("a block comment is its opening and closing token")?;
+                    Ok((open.trim().to_string(), close.trim().to_string()))
+                })
+                .collect::<Result<_, String>>()?;
+        }
+        "strings" => spec.strings = quote_chars(&items)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Reads a `strings` array, whose quotes are one character each.
+fn quote_chars(items: &[String]) -> Result<Vec<char>, String> {
+    items
+        .iter()
+        .map(|item| {
+            let mut chars = item.chars();
+            match (chars.next(), chars.next()) {
+                (Some(quote), None) => Ok(quote),
+                _ => Err("a string quote is a single character".to_string()),
+            }
+        })
+        .collect()
+}
+
+/// Checks a language has what it needs to run.
+fn finish(spec: GenericSpec) -> Result<GenericSpec, String> {
+    if spec.command.is_empty() {
+        return Err(format!("language `{}` has no `command`", spec.name));
+    }
+    if let Some(assignment) = &spec.assignment {
+        Regex::new(assignment)
+            .map_err(|err| format!("language `{}`: bad assignment regex: {err}", spec.name))?;
+    }
+    if !spec.print.contains("{expr}") || !spec.print.contains("{sentinel}") {
+        return Err(format!(
+            "language `{}` needs a `print` template with {{sentinel}} and {{expr}}",
+            spec.name
+        ));
+    }
+    Ok(spec)
+}
+
+enum Value {
+    String(String),
+    Array(Vec<String>),
+}
+
+impl Value {
+    fn parse(text: &str) -> Result<Self, String> {
+        if let Some(inner) = text.strip_prefix('[') {
+            let inner = inner
+                .strip_suffix(']')
+                .ok_or("arrays must close on the same line")?;
+            let mut items = Vec::new();
+            let mut rest = inner.trim();
+            while !rest.is_empty() {
+                let (item, after) = read_string(rest)?;
+                items.push(item);
+                rest = after.trim_start();
+                rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+            }
+            return Ok(Value::Array(items));
+        }
+        let (value, rest) = read_string(text)?;
+        if !rest.trim().is_empty() {
+            return Err("unexpected text after the value".into());
+        }
+        Ok(Value::String(value))
+    }
+}
+
+/// Reads a basic (`"..."`) or literal (`'...'`) string off the front of
+/// `text`, returning it and the remaining text.
+fn read_string(text: &str) -> Result<(String, &str), String> {
+    if let Some(rest) = text.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated string")?;
+        return Ok((rest[..end].to_string(), &rest[end + 1..]));
+    }
+    let rest = text.strip_prefix('"').ok_or("expected a quoted string")?;
+
+    let mut value = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '"' => return Ok((value, &rest[idx + 1..])),
+            '\\' => value.push(match chars.next().map(|(_, c)| c) {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                _ => return Err("unknown escape".into()),
+            }),
+            _ => value.push(ch),
+        }
+    }
+    Err("unterminated string".into())
+}
+
+fn unquote_key(key: &str) -> &str {
+    key.trim()
+        .strip_prefix('"')
+        .and_then(|k| k.strip_suffix('"'))
+        .unwrap_or(key.trim())
+}
+
+/// Removes a `#` comment that isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (idx, ch) in line.char_indices() {
+        match (quote, ch) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '#') => return &line[..idx],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_language_tables() {
+        let config = parse_config(
+            r#"
+# Languages run through their interpreters.
+[languages.node]
+command = "node"
+args = ["-", "--no-warnings"]  # read the script on stdin
+comment = "//"
+marker = "//="
+print = 'console.log("{sentinel}", {expr})'
+assignment = '^(?:const|let|var)\s+(\w+)\s*='
+strings = ['"', "'", "`"]
+
+[other]
+command = "ignored"
+
+[languages.ruby]
+command = "ruby"
+print = "puts \"{sentinel}\" + ({expr}).to_s" # trailing
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.languages.len(), 2);
+        let node = config.language("node").unwrap();
+        assert_eq!(node.args, vec!["-", "--no-warnings"]);
+        assert_eq!(node.comment.as_deref(), Some("//"));
+        assert_eq!(node.print, "console.log(\"{sentinel}\", {expr})");
+        assert_eq!(
+            node.assignment.as_deref(),
+            Some(r"^(?:const|let|var)\s+(\w+)\s*=")
+        );
+        assert_eq!(node.strings, Some(vec!['"', '\'', '`']));
+        let ruby = config.language("ruby").unwrap();
+        assert_eq!(ruby.print, "puts \"{sentinel}\" + ({expr}).to_s");
+        assert!(ruby.args.is_empty());
+        assert_eq!(ruby.strings, None);
+    }
+
+    #[test]
+    fn parses_comment_tables() {
+        let config = parse_config(
+            r#"
+[comments.proto]
+extensions = ["proto"]
+line = ["//"]
+block = ["/* */", "{- -}"]
+strings = ['"', "'"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.comments,
+            vec![CommentSpec {
+                extensions: vec!["proto".into()],
+                line: vec!["//".into()],
+                block: vec![("/*".into(), "*/".into()), ("{-".into(), "-}".into())],
+                strings: vec!['"', '\''],
+            }]
+        );
+        assert!(
+            parse_config(
+                "[comments.x]
+block = [\"/*\"]"
+            )
+            .is_err()
+        );
+        assert!(
+            parse_config(
+                "[comments.x]
+strings = [\"''\"]"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn parses_plugin_tables() {
+        let config = parse_config(
+            r#"
+[plugins.dsl]
+path = "tools/dsl-equals"
+comment = "--"
+strings = ['"']
+extensions = ["dsl"]
+
+[plugins.calc]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.plugin("dsl"),
+            Some(&PluginSpec {
+                name: "dsl".into(),
+                path: Some(PathBuf::from("tools/dsl-equals")),
+                comment: Some("--".into()),
+                strings: Some(vec!['"']),
+                extensions: vec!["dsl".into()],
+                ..PluginSpec::default()
+            })
+        );
+        assert_eq!(config.plugin("calc").unwrap().path, None);
+        assert!(
+            parse_config(
+                "[plugins.x]
+comment = [\"--\"]"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_languages() {
+        assert!(parse_config("[languages.x]\ncommand = \"x\"").is_err());
+        assert!(parse_config("[languages.x]\nprint = \"{sentinel}{expr}\"").is_err());
+        assert!(parse_config("[languages.x]\ncommand = [\"x\"]").is_err());
+        assert!(parse_config("[languages.x\n").is_err());
+        assert!(
+            parse_config(
+                "[languages.x]\ncommand = \"x\"\nprint = \"{sentinel}{expr}\"\nassignment = \"(\""
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Marker dialects: the annotation styles of other tools, which equals can
+//! evaluate in (`--marker xmpfilter`) and convert between (`equals
+//! convert`).
+
+use crate::lang::{CodeLine, Lexer, split_line};
+
+#[derive(Debug, PartialEq)]
+pub struct Dialect {
+    pub name: &'static str,
+    pub marker: &'static str,
+    /// The line comment token that may follow a result.
+    pub comment: &'static str,
+    /// The string literals of the languages it annotates.
+    pub lexer: &'static Lexer,
+    /// Whether the marker can sit inside a comment (`// 2 + 2 #=`), so
+    /// comment mode finds it. Other markers annotate a line of code.
+    pub in_comments: bool,
+}
+
+pub const DIALECTS: &[Dialect] = &[
+    Dialect {
+        name: "equals",
+        marker: "#=",
+        comment: "#",
+        lexer: &Lexer::PYTHON,
+        in_comments: true,
+    },
+    // Ruby's xmpfilter and seeing_is_believing. Ruby quotes strings with
+    // `"`, `'` and backticks, like the default lexer.
+    Dialect {
+        name: "xmpfilter",
+        marker: "# =>",
+        comment: "#",
+        lexer: &Lexer::DEFAULT,
+        in_comments: false,
+    },
+    // Quokka and other JavaScript tools.
+    Dialect {
+        name: "quokka",
+        marker: "// =>",
+        comment: "//",
+        lexer: &Lexer::DEFAULT,
+        in_comments: false,
+    },
+];
+
+/// Finds a dialect by name or by its marker.
+pub fn find_dialect(value: &str) -> Option<&'static Dialect> {
+    DIALECTS
+        .iter()
+        .find(|dialect| dialect.name.eq_ignore_ascii_case(value) || dialect.marker == value)
+}
+
+/// The marker a `--marker` value stands for: a dialect's, or the value
+/// itself.
+pub fn dialect_marker(value: &str) -> &str {
+    find_dialect(value).map_or(value, |dialect| dialect.marker)
+}
+
+/// Whether `marker` is a dialect's comment after a line of code (`# =>`),
+/// which comment mode can't see since it only evaluates comments.
+pub fn marks_code(marker: &str) -> bool {
+    find_dialect(marker).is_some_and(|dialect| !dialect.in_comments)
+}
+
+/// Rewrites every `from` marker in `text` as `to`, keeping the results,
+/// trailing comments and indentation. `comment` is the token that ends a
+/// result, and `lexer` finds the string literals markers may hide in.
+pub fn convert(text: &str, from: &str, to: &str, comment: &str, lexer: &Lexer) -> String {
+    let mut out: Vec<String> = text
+        .lines()
+        .map(|line| convert_line(line, from, to, comment, lexer))
+        .collect();
+    if text.ends_with('\n') {
+        out.push(String::new());
+    }
+    out.join("\n")
+}
+
+fn convert_line(line: &str, from: &str, to: &str, comment: &str, lexer: &Lexer) -> String {
+    let parsed = split_line(line, from, comment, lexer, |_| None);
+    let (code, result, trailing) = match parsed {
+        // A comment that merely starts like a marker (`# => returns nil`)
+        // annotates nothing.
+        CodeLine::Code { .. } => return line.to_string(),
+        CodeLine::Eval { code, .. } | CodeLine::EvalAssignment { code, .. }
+            if code.trim().is_empty() =>
+        {
+            return line.to_string();
+        }
+        CodeLine::Eval {
+            code,
+            result,
+            comment,
+            ..
+        }
+        | CodeLine::EvalAssignment {
+            code,
+            result,
+            comment,
+            ..
+        } => (code, result, comment),
+    };
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let mut converted = format!("{indent}{code} {to}");
+    for part in result.into_iter().chain(trailing) {
+        converted.push(' ');
+        converted.push_str(part.trim());
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_dialects_by_name_or_marker() {
+        assert_eq!(dialect_marker("xmpfilter"), "# =>");
+        assert_eq!(dialect_marker("Quokka"), "// =>");
+        assert_eq!(dialect_marker("%="), "%=");
+        assert_eq!(find_dialect("# =>").map(|d| d.name), Some("xmpfilter"));
+    }
+
+    #[test]
+    fn converts_between_dialects() {
+        let text = "x = [1, 2] # the list\nx.sum # => 3\n  x.size # => 2 # count\nx # =>\n";
+        assert_eq!(
+            convert(text, "# =>", "#=", "#", &Lexer::DEFAULT),
+            "x = [1, 2] # the list\nx.sum #= 3\n  x.size #= 2 # count\nx #=\n"
+        );
+
+        let js = "const a = 2;\na * 3 //= 6\n";
+        assert_eq!(
+            convert(js, "//=", "// =>", "//", &Lexer::DEFAULT),
+            "const a = 2;\na * 3 // => 6\n"
+        );
+    }
+
+    #[test]
+    fn prose_comments_and_strings_are_kept() {
+        let text = "# => returns nil\n  # =>\nputs '# => x' # => nil\n";
+        assert_eq!(
+            convert(text, "# =>", "#=", "#", &Lexer::DEFAULT),
+            "# => returns nil\n  # =>\nputs '# => x' #= nil\n"
+        );
+
+        // Numbat has no single-quoted strings to hide the marker in.
+        let numbat = "f' # => 'a'\n";
+        assert_eq!(convert(numbat, "# =>", "#=", "#", &Lexer::DEFAULT), numbat);
+        assert_eq!(
+            convert(numbat, "# =>", "#=", "#", &Lexer::NUMBAT),
+            "f' #= 'a'\n"
+        );
+    }
+}
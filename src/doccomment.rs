@@ -0,0 +1,318 @@
+use crate::{
+    document::{Block, Document, Line, OutputFence},
+    lang::registry::Registry,
+    markdown::MarkdownParser,
+    parser::Parser,
+};
+
+/// Which kind of documentation the host file carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocStyle {
+    /// `///` and `//!` doc comments.
+    Rust,
+    /// Triple-quoted docstrings.
+    Python,
+}
+
+impl DocStyle {
+    pub fn for_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "rs" => Some(DocStyle::Rust),
+            "py" | "pyw" => Some(DocStyle::Python),
+            _ => None,
+        }
+    }
+}
+
+/// Parses fenced examples inside Rust doc comments and Python docstrings.
+///
+/// Each run of documentation is stripped of its comment prefix (`/// ` or
+/// the docstring indentation), parsed with `MarkdownParser` and the prefix
+/// restored as text. Only fenced blocks are evaluated. In Rust, untagged
+/// fences are Rust doctests and stay text; tag examples with a language
+/// equals knows (```` ```numbat ````) to evaluate them.
+pub struct DocCommentParser {
+    style: DocStyle,
+    registry: Registry,
+}
+
+impl DocCommentParser {
+    pub fn new(style: DocStyle) -> Self {
+        Self {
+            style,
+            registry: Registry::default(),
+        }
+    }
+
+    /// Tags examples with the languages of `registry`.
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    /// The prefix length of every documentation line, `None` elsewhere.
+    fn prefixes(&self, lines: &[&str]) -> Vec<Option<usize>> {
+        match self.style {
+            DocStyle::Rust => lines.iter().map(|line| rust_prefix(line)).collect(),
+            DocStyle::Python => python_prefixes(lines),
+        }
+    }
+
+    /// Parses one run of documentation lines, the first of which is line
+    /// `first` of the file, as Markdown.
+    fn parse_run(&self, first: usize, lines: &[&str], prefixes: &[usize]) -> Document {
+        let markdown = lines
+            .iter()
+            .zip(prefixes)
+            .map(|(line, &prefix)| &line[prefix..])
+            .collect::<Vec<_>>()
+            .join("\n");
+        let parsed = MarkdownParser::fenced_only()
+            .with_registry(&self.registry)
+            .parse(&markdown);
+        let mut parsed_lines = parsed.lines;
+        // A trailing empty doc line doesn't produce a Markdown line.
+        while parsed_lines.len() < lines.len() {
+            parsed_lines.push(Line { blocks: Vec::new() });
+        }
+
+        let outputs = parsed
+            .outputs
+            .into_iter()
+            .map(|output| OutputFence {
+                body: output.body.start + first..output.body.end + first,
+                closing: output.closing + first,
+                existing: output
+                    .existing
+                    .map(|existing| existing.start + first..existing.end + first),
+                text: None,
+                prefix: lines[output.closing][..prefixes[output.closing]].to_string(),
+            })
+            .collect();
+        let lines = parsed_lines
+            .into_iter()
+            .zip(lines.iter().zip(prefixes))
+            .map(|(parsed, (line, &prefix))| {
+                let mut blocks = vec![Block::Text((0, prefix), line[..prefix].to_string())];
+                for block in parsed.blocks {
+                    blocks.push(match block {
+                        Block::Code(_, code, session)
+                            if self.style == DocStyle::Rust && session.language.is_none() =>
+                        {
+                            Block::Text((0, 0), code)
+                        }
+                        other => other,
+                    });
+                }
+                Self::shift_line(blocks)
+            })
+            .collect();
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Moves the Markdown blocks after the restored prefix.
+    fn shift_line(blocks: Vec<Block>) -> Line {
+        let mut col = 0;
+        let blocks = blocks
+            .into_iter()
+            .map(|block| {
+                let start = col;
+                match block {
+                    Block::Text(_, text) => {
+                        col += text.len();
+                        Block::Text((start, col), text)
+                    }
+                    Block::Code(_, code, session) => {
+                        col += code.len();
+                        Block::Code((start, col), code, session)
+                    }
+                }
+            })
+            .filter(|block| !matches!(block, Block::Text(_, text) if text.is_empty()))
+            .collect();
+        Line { blocks }
+    }
+}
+
+/// `/// text` or `//! text`; the prefix includes one space after the token.
+fn rust_prefix(line: &str) -> Option<usize> {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+    let rest = trimmed
+        .strip_prefix("//!")
+        .or_else(|| trimmed.strip_prefix("///").filter(|r| !r.starts_with('/')))?;
+    let space = usize::from(rest.starts_with(' '));
+    Some(indent + 3 + space)
+}
+
+/// Marks the lines between a docstring's opening and closing quotes, with
+/// the docstring's indentation as their prefix.
+fn python_prefixes(lines: &[&str]) -> Vec<Option<usize>> {
+    let mut prefixes = Vec::with_capacity(lines.len());
+    // Closing quotes and indentation of the open docstring.
+    let mut open: Option<(&str, usize)> = None;
+
+    for line in lines {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+
+        match open {
+            Some((quote, _)) if line.contains(quote) => {
+                open = None;
+                prefixes.push(None);
+            }
+            Some((_, doc_indent)) => prefixes.push(Some(indent.min(doc_indent))),
+            None => {
+                let unprefixed = trimmed.trim_start_matches(['r', 'R', 'u', 'U']);
+                let quote = ["\"\"\"", "'''"]
+                    .into_iter()
+                    .find(|q| unprefixed.starts_with(q));
+                if let Some(quote) = quote
+                    && !unprefixed[3..].contains(quote)
+                {
+                    open = Some((quote, indent));
+                }
+                prefixes.push(None);
+            }
+        }
+    }
+
+    prefixes
+}
+
+impl Parser for DocCommentParser {
+    fn parse(&self, input: &str) -> Document {
+        let raw: Vec<&str> = input.lines().collect();
+        let prefixes = self.prefixes(&raw);
+        let mut lines = Vec::new();
+        let mut outputs = Vec::new();
+        let mut i = 0;
+
+        while i < raw.len() {
+            if prefixes[i].is_none() {
+                lines.push(Line::text(raw[i]));
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < raw.len() && prefixes[i].is_some() {
+                i += 1;
+            }
+            let run: Vec<usize> = prefixes[start..i].iter().flatten().copied().collect();
+            let doc = self.parse_run(start, &raw[start..i], &run);
+            lines.extend(doc.lines);
+            outputs.extend(doc.outputs);
+        }
+
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs,
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_doc_comment_examples_keep_their_prefix() {
+        let src = "\
+//! Crate docs with `inline` code.
+//!
+//! ```numbat
+//! 3 ft -> cm #=
+//! ```
+
+/// Screen buffer size.
+///
+/// ```
+/// let size = buffer_size();
+/// ```
+///
+///```fend
+///1920*1080*4 bytes -> MiB #=
+///```
+pub fn buffer_size() -> usize {
+    // 2 + 2 #=
+    1920 * 1080 * 4
+}";
+        let doc = DocCommentParser::new(DocStyle::Rust).parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("3 ft -> cm #=", Some("numbat")),
+                ("1920*1080*4 bytes -> MiB #=", Some("fend")),
+            ]
+        );
+        match &doc.lines[3].blocks[..] {
+            [Block::Text(_, prefix), Block::Code(..)] => assert_eq!(prefix, "//! "),
+            other => panic!("expected prefix and code, got {other:?}"),
+        }
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn python_docstring_examples_strip_indentation() {
+        let src = "\
+def area(r):
+    \"\"\"Area of a circle.
+
+    ```python
+    3.14159 * 2 ** 2 #=
+    ```
+    \"\"\"
+    return 3.14159 * r ** 2 #=
+
+x = '''not a docstring example'''";
+        let doc = DocCommentParser::new(DocStyle::Python).parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("3.14159 * 2 ** 2 #=", Some("python"))]
+        );
+        match &doc.lines[4].blocks[0] {
+            Block::Text(_, prefix) => assert_eq!(prefix, "    "),
+            other => panic!("expected indentation text, got {other:?}"),
+        }
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn python_untagged_fences_use_document_language() {
+        let src = "\"\"\"\n```\n2 ** 8 #=\n```\n\"\"\"";
+        let doc = DocCommentParser::new(DocStyle::Python).parse(src);
+        assert_eq!(doc.code_blocks(), vec![("2 ** 8 #=", None)]);
+    }
+
+    #[test]
+    fn doc_comments_have_no_front_matter() {
+        let src =
+            "/// ---\n/// equals: { sessions: isolated }\n/// ---\n/// ```fend\n/// 1 #=\n/// ```";
+        let doc = DocCommentParser::new(DocStyle::Rust).parse(src);
+        match &doc.lines[4].blocks[..] {
+            [_, Block::Code(_, _, session)] => assert_eq!(session.name, None),
+            other => panic!("expected prefix and code, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn output_blocks_keep_the_doc_prefix() {
+        let src = "/// ```python {output=true}\n/// print(1)\n/// ```\nfn f() {}";
+        let mut doc = DocCommentParser::new(DocStyle::Rust).parse(src);
+        assert_eq!(doc.outputs[0].body, 1..2);
+        doc.outputs[0].text = Some("1\n".into());
+        assert_eq!(
+            doc.reconstruct(),
+            "/// ```python {output=true}\n/// print(1)\n/// ```\n///\n/// ```output\n/// 1\n/// ```\nfn f() {}"
+        );
+    }
+}
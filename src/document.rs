@@ -1,20 +1,79 @@
 use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::table::Table;
 
 #[derive(Debug)]
 pub struct Document {
     pub lines: Vec<Line>,
+    /// Pipe tables whose columns are re-aligned when the document is rebuilt.
+    pub tables: Vec<Table>,
+    /// Fences whose printed output is written into a following block.
+    pub outputs: Vec<OutputFence>,
+    /// Problems found while parsing, as line index and message, reported
+    /// alongside evaluation errors.
+    pub errors: Vec<(usize, String)>,
+}
+
+/// A fence tagged `{output=true}`. Its captured stdout replaces the body of
+/// the ```` ```output ```` block after it, which is created if missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputFence {
+    /// Line indices of the fence body.
+    pub body: Range<usize>,
+    /// Line index of the closing fence.
+    pub closing: usize,
+    /// Line indices of an existing output block, delimiters included.
+    pub existing: Option<Range<usize>>,
+    /// Captured output, once evaluated.
+    pub text: Option<String>,
+    /// Written before each line of the output block, like the `/// ` of a
+    /// doc comment.
+    pub prefix: String,
 }
 
 #[derive(Debug)]
 pub struct Line {
-    pub number: usize,
     pub blocks: Vec<Block>,
 }
 
+impl Line {
+    /// A line kept exactly as written.
+    pub fn text(line: &str) -> Self {
+        Self {
+            blocks: vec![Block::Text((0, line.len()), line.to_string())],
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Block {
     Text((usize, usize), String),
-    Code((usize, usize), String),
+    Code((usize, usize), String, Session),
+}
+
+/// Identifies the interpreter session a code block is evaluated in.
+/// Blocks in the same session see each other's definitions; the default
+/// session is shared by every block that doesn't ask for its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Session {
+    /// Language override; `None` means the document's language.
+    pub language: Option<String>,
+    pub name: Option<String>,
+}
+
+impl Session {
+    pub fn named(name: impl Into<String>) -> Self {
+        Self {
+            language: None,
+            name: Some(name.into()),
+        }
+    }
+
+    pub fn with_language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -42,41 +101,136 @@ pub struct CodeBlockUpdate {
     pub content: String,
 }
 
+/// What evaluating one session produced.
+#[derive(Debug, Clone, Default)]
+pub struct Evaluation {
+    pub updates: Vec<CodeBlockUpdate>,
+    /// Printed output of each requested capture range, in order.
+    pub outputs: Vec<String>,
+    /// Errors the interpreter reported, for the user's terminal.
+    pub errors: Vec<String>,
+}
+
 impl Document {
     pub fn reconstruct(&self) -> String {
-        self.lines
+        let mut rendered = self.render_lines();
+
+        // Back to front, so earlier line indices stay valid.
+        for output in self.outputs.iter().rev() {
+            let Some(text) = &output.text else {
+                continue;
+            };
+            let prefix = &output.prefix;
+            let mut block = vec![format!("{prefix}```output")];
+            block.extend(text.lines().map(|line| format!("{prefix}{line}")));
+            block.push(format!("{prefix}```"));
+
+            match &output.existing {
+                Some(existing) => {
+                    rendered.splice(existing.clone(), block);
+                }
+                None => {
+                    block.insert(0, prefix.trim_end().to_string());
+                    let at = output.closing + 1;
+                    rendered.splice(at..at, block);
+                }
+            }
+        }
+
+        rendered.join("\n")
+    }
+
+    /// Renders each line with its current block contents, realigning tables.
+    pub fn render_lines(&self) -> Vec<String> {
+        let mut rendered: Vec<String> = self
+            .lines
             .iter()
             .map(|line| {
                 // Sort blocks by start column to ensure correct ordering
                 let mut blocks = line.blocks.clone();
                 blocks.sort_by_key(|b| match b {
                     Block::Text((start, _), _) => *start,
-                    Block::Code((start, _), _) => *start,
+                    Block::Code((start, _), _, _) => *start,
                 });
 
                 blocks
                     .iter()
                     .map(|block| match block {
                         Block::Text(_, text) => text.clone(),
-                        Block::Code(_, code) => code.clone(),
+                        Block::Code(_, code, _) => code.clone(),
                     })
                     .collect::<String>()
             })
-            .collect::<Vec<_>>()
-            .join("\n")
+            .collect();
+
+        for table in &self.tables {
+            table.realign(&mut rendered);
+        }
+
+        rendered
     }
 
-    pub fn evaluate_with<F>(&mut self, evaluator: F)
+    /// Every code block's content and language override, in order.
+    #[cfg(test)]
+    pub fn code_blocks(&self) -> Vec<(&str, Option<&str>)> {
+        self.lines
+            .iter()
+            .flat_map(|line| &line.blocks)
+            .filter_map(|block| match block {
+                Block::Code(_, code, session) => Some((code.as_str(), session.language.as_deref())),
+                Block::Text(..) => None,
+            })
+            .collect()
+    }
+
+    /// Assigns `language` to every code block that didn't pick one, so that
+    /// blocks naming the document language explicitly share its session.
+    pub fn set_default_language(&mut self, language: &str) {
+        for line in &mut self.lines {
+            for block in &mut line.blocks {
+                if let Block::Code(_, _, session) = block
+                    && session.language.is_none()
+                {
+                    session.language = Some(language.to_string());
+                }
+            }
+        }
+    }
+
+    /// Runs `evaluator` once per session, in order of first appearance, and
+    /// writes the returned updates back into the document.
+    #[cfg(test)]
+    pub fn evaluate_with<F>(&mut self, mut evaluator: F)
+    where
+        F: FnMut(&Session, &[CodeBlock]) -> Vec<CodeBlockUpdate>,
+    {
+        self.evaluate_capturing_with(|session, blocks, _| Evaluation {
+            updates: evaluator(session, blocks),
+            ..Evaluation::default()
+        });
+    }
+
+    /// Like `evaluate_with`, but also passes the ranges of each session's
+    /// blocks that belong to output fences, and stores what they printed.
+    pub fn evaluate_capturing_with<F>(&mut self, mut evaluator: F)
     where
-        F: FnOnce(&[CodeBlock]) -> Vec<CodeBlockUpdate>,
+        F: FnMut(&Session, &[CodeBlock], &[Range<usize>]) -> Evaluation,
     {
         let mut extracted: Vec<(BlockId, String)> = Vec::new();
+        let mut sessions: Vec<(Session, Vec<BlockId>)> = Vec::new();
+        // Line index of each extracted block.
+        let mut block_lines: Vec<usize> = Vec::new();
 
-        for line in &mut self.lines {
+        for (line_idx, line) in self.lines.iter_mut().enumerate() {
             for block in &mut line.blocks {
-                if let Block::Code(_, code) = block {
+                if let Block::Code(_, code, session) = block {
                     let id = BlockId::new(extracted.len());
                     extracted.push((id, std::mem::take(code)));
+                    block_lines.push(line_idx);
+                    match sessions.iter_mut().find(|(s, _)| s == session) {
+                        Some((_, members)) => members.push(id),
+                        None => sessions.push((session.clone(), vec![id])),
+                    }
                 }
             }
         }
@@ -85,24 +239,45 @@ impl Document {
             return;
         }
 
-        let view: Vec<CodeBlock> = extracted
-            .iter()
-            .map(|(id, content)| CodeBlock {
-                id: *id,
-                content: content.as_str(),
-            })
-            .collect();
-
-        let updates = evaluator(&view);
         let mut updates_map: HashMap<BlockId, String> = HashMap::new();
-        for update in updates {
-            updates_map.insert(update.id, update.content);
+        for (session, members) in &sessions {
+            let view: Vec<CodeBlock> = members
+                .iter()
+                .map(|&id| CodeBlock {
+                    id,
+                    content: extracted[id.index()].1.as_str(),
+                })
+                .collect();
+
+            // Output fences whose blocks all live in this session.
+            let mut fences = Vec::new();
+            let mut capture = Vec::new();
+            for (fence_idx, fence) in self.outputs.iter().enumerate() {
+                let positions: Vec<usize> = members
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, id)| fence.body.contains(&block_lines[id.index()]))
+                    .map(|(pos, _)| pos)
+                    .collect();
+                if let (Some(&first), Some(&last)) = (positions.first(), positions.last()) {
+                    fences.push(fence_idx);
+                    capture.push(first..last + 1);
+                }
+            }
+
+            let evaluation = evaluator(session, &view, &capture);
+            for update in evaluation.updates {
+                updates_map.insert(update.id, update.content);
+            }
+            for (fence_idx, text) in fences.into_iter().zip(evaluation.outputs) {
+                self.outputs[fence_idx].text = Some(text);
+            }
         }
 
         let mut extracted_iter = extracted.into_iter();
         for line in &mut self.lines {
             for block in &mut line.blocks {
-                if let Block::Code(_, code) = block {
+                if let Block::Code(_, code, _) = block {
                     let (id, original) = extracted_iter
                         .next()
                         .expect("mismatched number of code blocks during evaluation");
@@ -122,20 +297,26 @@ mod tests {
     use super::*;
     use std::cell::Cell;
 
-    fn code_line(number: usize, content: &str) -> Line {
+    fn code_line(content: &str) -> Line {
         Line {
-            number,
-            blocks: vec![Block::Code((0, content.len()), content.to_string())],
+            blocks: vec![Block::Code(
+                (0, content.len()),
+                content.to_string(),
+                Session::default(),
+            )],
         }
     }
 
     #[test]
     fn evaluate_with_applies_partial_updates() {
         let mut doc = Document {
-            lines: vec![code_line(1, "x = 1"), code_line(2, "x + 1 #=")],
+            lines: vec![code_line("x = 1"), code_line("x + 1 #=")],
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
         };
 
-        doc.evaluate_with(|blocks| {
+        doc.evaluate_with(|_, blocks| {
             assert_eq!(blocks.len(), 2);
             assert_eq!(blocks[0].id.index(), 0);
             assert_eq!(blocks[1].id.index(), 1);
@@ -151,12 +332,12 @@ mod tests {
         let line2 = &doc.lines[1].blocks[0];
 
         match line1 {
-            Block::Code(_, text) => assert_eq!(text, "x = 1"),
+            Block::Code(_, text, _) => assert_eq!(text, "x = 1"),
             _ => panic!("expected code block for line 1"),
         }
 
         match line2 {
-            Block::Code(_, text) => assert_eq!(text, "x + 1 #= 2"),
+            Block::Code(_, text, _) => assert_eq!(text, "x + 1 #= 2"),
             _ => panic!("expected code block for line 2"),
         }
     }
@@ -164,32 +345,107 @@ mod tests {
     #[test]
     fn evaluate_with_keeps_original_when_no_updates() {
         let mut doc = Document {
-            lines: vec![code_line(1, "print('hi')")],
+            lines: vec![code_line("print('hi')")],
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
         };
 
-        doc.evaluate_with(|blocks| {
+        doc.evaluate_with(|_, blocks| {
             assert_eq!(blocks.len(), 1);
             assert_eq!(blocks[0].content, "print('hi')");
             Vec::new()
         });
 
         match &doc.lines[0].blocks[0] {
-            Block::Code(_, text) => assert_eq!(text, "print('hi')"),
+            Block::Code(_, text, _) => assert_eq!(text, "print('hi')"),
             _ => panic!("expected code block to remain unchanged"),
         }
     }
 
+    #[test]
+    fn evaluate_with_groups_blocks_by_session() {
+        let mut doc = Document {
+            lines: vec![
+                code_line("a"),
+                Line {
+                    blocks: vec![Block::Code((0, 1), "b".into(), Session::named("other"))],
+                },
+                code_line("c"),
+            ],
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        let mut calls = Vec::new();
+        doc.evaluate_with(|session, blocks| {
+            let contents: Vec<&str> = blocks.iter().map(|b| b.content).collect();
+            calls.push((session.name.clone(), contents.join(",")));
+            blocks
+                .iter()
+                .map(|b| CodeBlockUpdate {
+                    id: b.id,
+                    content: b.content.to_uppercase(),
+                })
+                .collect()
+        });
+
+        assert_eq!(
+            calls,
+            vec![
+                (None, "a,c".to_string()),
+                (Some("other".into()), "b".into())
+            ]
+        );
+        assert_eq!(doc.reconstruct(), "A\nB\nC");
+    }
+
+    #[test]
+    fn default_language_merges_explicit_sessions() {
+        let mut doc = Document {
+            lines: vec![Line {
+                blocks: vec![
+                    Block::Code((0, 1), "a".into(), Session::default()),
+                    Block::Code((1, 2), "b".into(), Session::default().with_language("fend")),
+                    Block::Code(
+                        (2, 3),
+                        "c".into(),
+                        Session::default().with_language("numbat"),
+                    ),
+                ],
+            }],
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        };
+        doc.set_default_language("numbat");
+
+        let mut calls = Vec::new();
+        doc.evaluate_with(|session, blocks| {
+            calls.push((session.language.clone(), blocks.len()));
+            Vec::new()
+        });
+
+        assert_eq!(
+            calls,
+            vec![(Some("numbat".into()), 2), (Some("fend".into()), 1)]
+        );
+    }
+
     #[test]
     fn evaluate_with_skips_when_no_code_blocks() {
         let mut doc = Document {
             lines: vec![Line {
-                number: 1,
                 blocks: vec![Block::Text((0, 4), "text".into())],
             }],
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
         };
 
         let called = Cell::new(false);
-        doc.evaluate_with(|_| {
+        doc.evaluate_with(|_, _| {
             called.set(true);
             Vec::new()
         });
@@ -204,4 +460,78 @@ mod tests {
             _ => panic!("text block should remain untouched"),
         }
     }
+
+    #[test]
+    fn output_fences_get_their_capture_range() {
+        let mut doc = Document {
+            lines: vec![
+                code_line("a"),
+                Line {
+                    blocks: vec![Block::Text((0, 3), "```".into())],
+                },
+                code_line("b"),
+                code_line("c"),
+                Line {
+                    blocks: vec![Block::Text((0, 3), "```".into())],
+                },
+            ],
+            tables: Vec::new(),
+            outputs: vec![OutputFence {
+                body: 2..4,
+                closing: 4,
+                existing: None,
+                text: None,
+                prefix: String::new(),
+            }],
+            errors: Vec::new(),
+        };
+
+        doc.evaluate_capturing_with(|_, blocks, capture| {
+            assert_eq!(blocks.len(), 3);
+            assert_eq!(capture.len(), 1);
+            assert_eq!(capture[0], 1..3);
+            Evaluation {
+                outputs: vec!["hello\n".into()],
+                ..Evaluation::default()
+            }
+        });
+
+        assert_eq!(
+            doc.reconstruct(),
+            "a\n```\nb\nc\n```\n\n```output\nhello\n```"
+        );
+    }
+
+    #[test]
+    fn existing_output_block_is_replaced() {
+        let text = |s: &str| Line {
+            blocks: vec![Block::Text((0, s.len()), s.into())],
+        };
+        let doc = Document {
+            lines: vec![
+                text("```"),
+                code_line("print(1)"),
+                text("```"),
+                text("```output"),
+                text("old"),
+                text("stale"),
+                text("```"),
+                text("after"),
+            ],
+            tables: Vec::new(),
+            outputs: vec![OutputFence {
+                body: 1..2,
+                closing: 2,
+                existing: Some(3..7),
+                text: Some("1\n".into()),
+                prefix: String::new(),
+            }],
+            errors: Vec::new(),
+        };
+
+        assert_eq!(
+            doc.reconstruct(),
+            "```\nprint(1)\n```\n```output\n1\n```\nafter"
+        );
+    }
 }
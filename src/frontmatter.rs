@@ -0,0 +1,198 @@
+//! Reads the `equals` settings from a leading YAML front matter block.
+//!
+//! Only the small subset of YAML needed for the `equals` key is understood:
+//! either a flow mapping (`equals: { language: numbat, precision: 4 }`) or an
+//! indented block mapping underneath `equals:`. Other keys are ignored.
+
+/// How code blocks in a document share interpreter state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionMode {
+    /// Every block runs in one session and sees earlier definitions.
+    #[default]
+    Shared,
+    /// Every block runs in a fresh session of its own.
+    Isolated,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct FrontMatter {
+    /// Number of lines the block occupies, including both delimiters.
+    pub line_count: usize,
+    pub language: Option<String>,
+    pub marker: Option<String>,
+    pub sessions: SessionMode,
+    pub precision: Option<usize>,
+}
+
+/// Parses the front matter at the very start of `input`, if there is one.
+pub fn parse_front_matter(input: &str) -> Option<FrontMatter> {
+    let mut lines = input.lines();
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+
+    let body: Vec<&str> = lines
+        .by_ref()
+        .take_while(|line| !matches!(line.trim_end(), "---" | "..."))
+        .collect();
+
+    // The closing delimiter must exist, otherwise this is just a thematic break.
+    let line_count = body.len() + 2;
+    if input.lines().count() < line_count {
+        return None;
+    }
+
+    let mut front_matter = FrontMatter {
+        line_count,
+        ..FrontMatter::default()
+    };
+
+    for (key, value) in equals_entries(&body) {
+        match key.as_str() {
+            "language" | "lang" => front_matter.language = Some(value.to_ascii_lowercase()),
+            "marker" => front_matter.marker = Some(value),
+            "sessions" | "session" => match value.as_str() {
+                "isolated" => front_matter.sessions = SessionMode::Isolated,
+                "shared" => front_matter.sessions = SessionMode::Shared,
+                _ => {}
+            },
+            "precision" => front_matter.precision = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    Some(front_matter)
+}
+
+/// Collects the key/value pairs nested under the top-level `equals` key.
+fn equals_entries(body: &[&str]) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut iter = body.iter().peekable();
+
+    while let Some(line) = iter.next() {
+        let Some(rest) = line.strip_prefix("equals:") else {
+            continue;
+        };
+
+        let rest = strip_comment(rest).trim();
+        if let Some(inner) = rest.strip_prefix('{').and_then(|r| r.strip_suffix('}')) {
+            entries.extend(split_flow(inner).into_iter().filter_map(key_value));
+        } else if rest.is_empty() {
+            while let Some(nested) = iter.next_if(|l| l.starts_with([' ', '\t']) || l.is_empty()) {
+                if let Some(entry) = key_value(strip_comment(nested).trim()) {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+
+    entries
+}
+
+/// Splits the inside of a flow mapping on commas that are not quoted.
+fn split_flow(inner: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut start = 0;
+
+    for (idx, ch) in inner.char_indices() {
+        match (quote, ch) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, ',') => {
+                parts.push(&inner[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&inner[start..]);
+    parts
+}
+
+fn key_value(entry: &str) -> Option<(String, String)> {
+    let (key, value) = entry.split_once(':')?;
+    let key = key.trim();
+    if key.is_empty() {
+        return None;
+    }
+    Some((key.to_string(), unquote(value.trim()).to_string()))
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+/// Removes a trailing YAML comment (` # ...`) that isn't inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut prev = ' ';
+    for (idx, ch) in line.char_indices() {
+        match (quote, ch) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '#') if prev.is_whitespace() => return &line[..idx],
+            _ => {}
+        }
+        prev = ch;
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_flow_mapping() {
+        let doc = "---\ntitle: Demo\nequals: { language: numbat, marker: \"#=\", sessions: isolated, precision: 4 }\n---\n# Body\n";
+        let fm = parse_front_matter(doc).expect("front matter");
+
+        assert_eq!(fm.line_count, 4);
+        assert_eq!(fm.language.as_deref(), Some("numbat"));
+        assert_eq!(fm.marker.as_deref(), Some("#="));
+        assert_eq!(fm.sessions, SessionMode::Isolated);
+        assert_eq!(fm.precision, Some(4));
+    }
+
+    #[test]
+    fn parses_block_mapping() {
+        let doc = "---\nequals:\n  language: Fend # calculator\n  marker: '=>'\nauthor: me\n---\n";
+        let fm = parse_front_matter(doc).expect("front matter");
+
+        assert_eq!(fm.line_count, 6);
+        assert_eq!(fm.language.as_deref(), Some("fend"));
+        assert_eq!(fm.marker.as_deref(), Some("=>"));
+        assert_eq!(fm.sessions, SessionMode::Shared);
+        assert_eq!(fm.precision, None);
+    }
+
+    #[test]
+    fn ignores_documents_without_front_matter() {
+        assert_eq!(parse_front_matter("# Title\n---\n"), None);
+        assert_eq!(
+            parse_front_matter("---\nequals: { language: fend }\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn front_matter_without_equals_key_has_defaults() {
+        let fm = parse_front_matter("---\ntitle: Notes\n---\ntext").expect("front matter");
+        assert_eq!(
+            fm,
+            FrontMatter {
+                line_count: 3,
+                ..FrontMatter::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! A minimal JSON reader and string encoder, for notebooks and language
+//! plugins.
+
+use std::ops::Range;
+
+/// A JSON string with the byte span of its literal (quotes included).
+#[derive(Debug, Clone)]
+pub struct JsonString {
+    pub value: String,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug)]
+pub enum Json {
+    Object(Vec<(String, Json)>),
+    Array(Vec<Json>),
+    String(JsonString),
+    /// Numbers, booleans and null, as written.
+    Scalar(String),
+}
+
+/// Parses a whole JSON document.
+pub fn parse(text: &str) -> Result<Json, String> {
+    JsonReader::new(text).read_document()
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(&s.value),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Scalar(raw) => raw.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Minimal JSON reader that remembers where each string literal came from.
+struct JsonReader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> JsonReader<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    fn read_document(mut self) -> Result<Json, String> {
+        let value = self.read_value()?;
+        self.skip_whitespace();
+        if self.pos != self.text.len() {
+            return Err(self.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("JSON at byte {}: {message}", self.pos)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\n' | b'\r' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", byte as char)))
+        }
+    }
+
+    fn read_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.read_object(),
+            Some(b'[') => self.read_array(),
+            Some(b'"') => Ok(Json::String(self.read_string()?)),
+            Some(_) => {
+                let rest = &self.text[self.pos..];
+                let len = rest
+                    .find(|c: char| matches!(c, ',' | ']' | '}') || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                if len == 0 {
+                    return Err(self.error("expected a value"));
+                }
+                self.pos += len;
+                Ok(Json::Scalar(rest[..len].to_string()))
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn read_object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.read_string()?.value;
+            self.expect(b':')?;
+            entries.push((key, self.read_value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(entries));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn read_array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.read_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> Result<JsonString, String> {
+        let start = self.pos;
+        if self.peek() != Some(b'"') {
+            return Err(self.error("expected a string"));
+        }
+        self.pos += 1;
+
+        let mut value = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let Some(ch) = rest.chars().next() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += ch.len_utf8();
+            match ch {
+                '"' => break,
+                '\\' => value.push(self.read_escape()?),
+                _ => value.push(ch),
+            }
+        }
+
+        Ok(JsonString {
+            value,
+            span: start..self.pos,
+        })
+    }
+
+    fn read_escape(&mut self) -> Result<char, String> {
+        let Some(byte) = self.peek() else {
+            return Err(self.error("unterminated escape"));
+        };
+        self.pos += 1;
+        Ok(match byte {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let high = self.read_hex4()?;
+                if (0xD800..0xDC00).contains(&high) && self.text[self.pos..].starts_with("\\u") {
+                    self.pos += 2;
+                    let low = self.read_hex4()?;
+                    let combined = 0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00));
+                    char::from_u32(combined).unwrap_or('\u{FFFD}')
+                } else {
+                    char::from_u32(high).unwrap_or('\u{FFFD}')
+                }
+            }
+            _ => return Err(self.error("unknown escape")),
+        })
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.error("short unicode escape"))?;
+        let value =
+            u32::from_str_radix(digits, 16).map_err(|_| self.error("bad unicode escape"))?;
+        self.pos += 4;
+        Ok(value)
+    }
+}
+
+/// Encodes `value` as a JSON string literal the way Jupyter writes them
+/// (non-ASCII characters are kept as-is).
+pub fn encode_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
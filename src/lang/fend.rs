@@ -1,88 +1,144 @@
-use std::process::{Command, Stdio};
+use std::ops::Range;
+use std::process::Command;
 
 use crate::{
-    document::{CodeBlock, CodeBlockUpdate},
-    lang::{CodeLine, Language, split_line},
+    document::{CodeBlock, Evaluation},
+    lang::{
+        CodeLine, LangOptions, Language, Lexer, Markers, line_marker,
+        registry::LanguageInfo,
+        runner::{self, Channel, Execution, Protocol, ScriptRunner, Warm},
+        split_line, tangle_line,
+    },
 };
 
-const MARKER: &str = "#=";
-const COMMENT: &str = "#";
+pub const INFO: LanguageInfo = LanguageInfo {
+    name: "fend",
+    aliases: &["fd"],
+    extensions: &["fend", "fd"],
+    fences: &[],
+    marker: "#=",
+    comment: "#",
+    lexer: &Lexer::FEND,
+};
 
-pub struct FendLang;
+pub struct FendLang {
+    options: LangOptions,
+    marker: String,
+    comment: String,
+    warm: Option<Warm>,
+}
 
 impl FendLang {
-    pub fn new() -> Self {
-        Self
-    }
-
-    fn evaluate_in_place(&self, blocks: &mut [String]) {
-        let originals: Vec<String> = blocks.iter().cloned().collect();
-        let parsed: Vec<_> = originals
-            .iter()
-            .map(|line| split_line(line, MARKER, COMMENT, |_| None))
-            .collect();
-
-        let script = build_fend_script(&parsed);
-        if script.is_empty() {
-            return;
+    pub fn with_options(options: LangOptions) -> Self {
+        Self {
+            marker: options.marker_for(INFO.name, INFO.marker).to_string(),
+            comment: INFO.comment.to_string(),
+            warm: options.persistent.then(Warm::default),
+            options,
         }
+    }
+}
 
-        if let Some(output) = run_fend(&script) {
-            for line in output.lines() {
-                if let Some(rest) = line.strip_prefix("##RESULT:") {
-                    let mut parts = rest.trim_start().splitn(2, ' ');
-                    let idx_str = parts.next().unwrap_or_default();
-                    let value = parts.next().unwrap_or("").trim();
-
-                    if let Ok(idx) = idx_str.parse::<usize>() {
-                        if let Some(target) = blocks.get_mut(idx) {
-                            let reconstructed = parsed[idx].reconstruct(value);
-                            *target = reconstructed;
-                        }
-                    }
-                }
-            }
-        }
+impl Default for FendLang {
+    fn default() -> Self {
+        Self::with_options(LangOptions::default())
     }
 }
 
 impl Language for FendLang {
     fn name(&self) -> &str {
-        "fend"
+        INFO.name
     }
 
     fn eval_marker(&self) -> &str {
-        MARKER
+        &self.marker
     }
 
-    fn evaluate(&self, blocks: &[CodeBlock]) -> Vec<CodeBlockUpdate> {
-        if blocks.is_empty() {
-            return Vec::new();
-        }
+    fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    fn lexer(&self) -> &Lexer {
+        INFO.lexer
+    }
+
+    fn evaluate_capturing(&self, blocks: &[CodeBlock], capture: &[Range<usize>]) -> Evaluation {
+        runner::evaluate(self, blocks, capture)
+    }
+
+    fn tangle_line(&self, line: &str, markers: Markers) -> String {
+        let parsed = self.split(line);
+        tangle_line(
+            line,
+            &parsed,
+            markers,
+            |code| format!("println ({code})"),
+            |code, expected| format!("println ({code}) # expected: {expected}"),
+        )
+    }
+}
 
-        let mut working: Vec<String> = blocks.iter().map(|b| b.content.to_string()).collect();
-        self.evaluate_in_place(&mut working);
+impl ScriptRunner for FendLang {
+    fn options(&self) -> &LangOptions {
+        &self.options
+    }
+
+    fn split<'a>(&'a self, line: &'a str) -> CodeLine<'a> {
+        let marker = line_marker(line, self.eval_marker(), &INFO);
+        split_line(line, marker, self.comment(), INFO.lexer, |_| None)
+    }
 
-        blocks
+    fn fragments(
+        &self,
+        lines: &[CodeLine],
+        _originals: &[&str],
+        capture: &[Range<usize>],
+        protocol: &Protocol,
+    ) -> Vec<String> {
+        build_fend_statements(lines, capture, protocol)
+    }
+
+    fn program(&self, fragments: &[String]) -> String {
+        fragments
             .iter()
-            .zip(working.into_iter())
-            .filter_map(|(block, new_content)| {
-                if block.content == new_content {
-                    None
-                } else {
-                    Some(CodeBlockUpdate {
-                        id: block.id,
-                        content: new_content,
-                    })
-                }
-            })
-            .collect()
+            .filter(|fragment| !fragment.is_empty())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    fn run(&self, program: &str) -> Option<Execution> {
+        if program.trim().is_empty() {
+            return None;
+        }
+        Some(runner::run_reporting(
+            Command::new("fend").arg(program),
+            None,
+        ))
+    }
+
+    /// Fend has no line-by-line REPL, so an unchanged program replays.
+    fn warm(&self) -> Option<&Warm> {
+        self.warm.as_ref()
     }
 }
 
-fn build_fend_script(lines: &[CodeLine]) -> String {
-    let mut statements = Vec::new();
+/// The statements of each line. Fend values print on one line, so results
+/// follow their record tag unescaped.
+fn build_fend_statements(
+    lines: &[CodeLine],
+    capture: &[Range<usize>],
+    protocol: &Protocol,
+) -> Vec<String> {
+    let mut fragments = Vec::new();
     for (idx, line) in lines.iter().enumerate() {
+        let mut statements = Vec::new();
+        if let Some(group) = capture.iter().position(|range| range.start == idx) {
+            statements.push(format!(
+                "println \"{}\"",
+                protocol.tag(Channel::Begin, group)
+            ));
+        }
         match line {
             CodeLine::Code { code } => {
                 if !code.is_empty() {
@@ -91,32 +147,20 @@ fn build_fend_script(lines: &[CodeLine]) -> String {
             }
             CodeLine::Eval { code, .. } | CodeLine::EvalAssignment { code, .. } => {
                 if !code.is_empty() {
-                    statements.push(format!("print \"##RESULT:{} \"; println ({})", idx, code));
+                    statements.push(format!(
+                        "print \"{}\"; println ({})",
+                        protocol.tag(Channel::Result, idx),
+                        code
+                    ));
                 }
             }
         }
+        if capture.iter().any(|range| range.end == idx + 1) {
+            statements.push(format!("println \"{}\"", protocol.tag(Channel::End, 0)));
+        }
+        fragments.push(statements.join("; "));
     }
-    statements.join("; ")
-}
-
-fn run_fend(script: &str) -> Option<String> {
-    if script.trim().is_empty() {
-        return None;
-    }
-
-    let output = Command::new("fend")
-        .arg(script)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .ok()?;
-
-    if !output.status.success() {
-        return None;
-    }
-
-    Some(String::from_utf8_lossy(&output.stdout).to_string())
+    fragments
 }
 
 #[cfg(test)]
@@ -125,8 +169,8 @@ mod tests {
     use crate::document::{BlockId, CodeBlock};
 
     fn eval_blocks(blocks: &mut [String]) {
-        let lang = FendLang;
-        let snapshots: Vec<String> = blocks.iter().cloned().collect();
+        let lang = FendLang::default();
+        let snapshots: Vec<String> = blocks.to_vec();
         let code_blocks: Vec<CodeBlock> = snapshots
             .iter()
             .enumerate()
@@ -0,0 +1,278 @@
+use std::ops::Range;
+use std::process::Command;
+
+use regex::Regex;
+
+use crate::{
+    config::GenericSpec,
+    document::{CodeBlock, Evaluation},
+    lang::{
+        CodeLine, LangOptions, Language, Lexer, Markers,
+        runner::{self, Channel, Execution, Protocol, ScriptRunner, Warm},
+        split_line, tangle_line,
+    },
+};
+
+const MARKER: &str = "#=";
+const COMMENT: &str = "#";
+
+/// A language defined in `equals.toml`: the script goes to `command` on
+/// stdin and values are printed with the `print` template.
+///
+/// Values are printed as the language formats them, so a value spanning
+/// several lines only keeps its first.
+pub struct GenericLang {
+    spec: GenericSpec,
+    assignment: Option<Regex>,
+    options: LangOptions,
+    marker: String,
+    comment: String,
+    lexer: Lexer,
+    warm: Option<Warm>,
+}
+
+impl GenericLang {
+    pub fn new(spec: GenericSpec, options: LangOptions) -> Result<Self, String> {
+        let assignment = spec
+            .assignment
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|err| format!("language `{}`: bad assignment regex: {err}", spec.name))?;
+        let default_marker = spec.marker.as_deref().unwrap_or(MARKER);
+        Ok(Self {
+            marker: options.marker_for(&spec.name, default_marker).to_string(),
+            comment: spec.comment.clone().unwrap_or_else(|| COMMENT.to_string()),
+            lexer: spec
+                .strings
+                .as_deref()
+                .map_or(Lexer::DEFAULT, Lexer::with_quotes),
+            warm: options.persistent.then(Warm::default),
+            spec,
+            assignment,
+            options,
+        })
+    }
+
+    /// Fills in the print template.
+    fn print(&self, sentinel: &str, expr: &str) -> String {
+        self.spec
+            .print
+            .replace("{sentinel}", sentinel)
+            .replace("{expr}", expr)
+    }
+}
+
+impl Language for GenericLang {
+    fn name(&self) -> &str {
+        &self.spec.name
+    }
+
+    fn eval_marker(&self) -> &str {
+        &self.marker
+    }
+
+    fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    fn lexer(&self) -> &Lexer {
+        &self.lexer
+    }
+
+    fn evaluate_capturing(&self, blocks: &[CodeBlock], capture: &[Range<usize>]) -> Evaluation {
+        runner::evaluate(self, blocks, capture)
+    }
+
+    /// Markers become print statements without a sentinel; there's no
+    /// general way to write an assertion, so `assert` prints too.
+    fn tangle_line(&self, line: &str, markers: Markers) -> String {
+        let print = |code: &str| self.print("", code);
+        tangle_line(line, &self.split(line), markers, print, |code, _| {
+            print(code)
+        })
+    }
+}
+
+impl ScriptRunner for GenericLang {
+    fn options(&self) -> &LangOptions {
+        &self.options
+    }
+
+    fn split<'a>(&'a self, line: &'a str) -> CodeLine<'a> {
+        split_line(
+            line,
+            self.eval_marker(),
+            self.comment(),
+            &self.lexer,
+            |code| {
+                let captures = self.assignment.as_ref()?.captures(code)?;
+                Some(captures.get(1)?.as_str())
+            },
+        )
+    }
+
+    fn fragments(
+        &self,
+        lines: &[CodeLine],
+        originals: &[&str],
+        capture: &[Range<usize>],
+        protocol: &Protocol,
+    ) -> Vec<String> {
+        let mut fragments = Vec::new();
+        for (idx, line) in lines.iter().enumerate() {
+            let original = originals[idx];
+            let indent = &original[..original.len() - original.trim_start().len()];
+            let mut statements = Vec::new();
+            if let Some(group) = capture.iter().position(|range| range.start == idx) {
+                statements.push(self.print(&protocol.tag(Channel::Begin, group), "\"\""));
+            }
+            match line {
+                CodeLine::Code { code } => statements.push(format!("{indent}{code}")),
+                CodeLine::Eval { code, .. } => statements.push(format!(
+                    "{indent}{}",
+                    self.print(&protocol.tag(Channel::Result, idx), code)
+                )),
+                CodeLine::EvalAssignment { var, code, .. } => {
+                    statements.push(format!("{indent}{code}"));
+                    statements.push(format!(
+                        "{indent}{}",
+                        self.print(&protocol.tag(Channel::Result, idx), var)
+                    ));
+                }
+            }
+            if capture.iter().any(|range| range.end == idx + 1) {
+                statements.push(self.print(&protocol.tag(Channel::End, 0), "\"\""));
+            }
+            fragments.push(statements.join("\n"));
+        }
+        fragments
+    }
+
+    fn program(&self, fragments: &[String]) -> String {
+        fragments.join("\n") + "\n"
+    }
+
+    fn run(&self, program: &str) -> Option<Execution> {
+        let mut command = Command::new(&self.spec.command);
+        command.args(&self.spec.args);
+        Some(runner::run_reporting(&mut command, Some(program)))
+    }
+
+    fn warm(&self) -> Option<&Warm> {
+        self.warm.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+    use crate::document::BlockId;
+
+    fn python_as_generic() -> GenericLang {
+        let config = parse_config(
+            r#"
+[languages.py3]
+command = "python3"
+args = ["-"]
+print = 'print("{sentinel}", {expr})'
+assignment = '^([A-Za-z_]\w*)\s*=[^=]'
+"#,
+        )
+        .unwrap();
+        GenericLang::new(config.languages[0].clone(), LangOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn runs_configured_interpreter() {
+        let lang = python_as_generic();
+        let contents = ["x = 6", "y = x * 7 #=", "for i in range(2):", "    i #="];
+        let blocks: Vec<CodeBlock> = contents
+            .iter()
+            .enumerate()
+            .map(|(idx, content)| CodeBlock {
+                id: BlockId::new(idx),
+                content,
+            })
+            .collect();
+
+        let updates: Vec<String> = lang
+            .evaluate(&blocks)
+            .into_iter()
+            .map(|update| update.content)
+            .collect();
+        assert_eq!(updates, vec!["y = x * 7 #= 42", "    i #= 1"]);
+    }
+
+    #[test]
+    fn unchanged_programs_replay_when_warm() {
+        let config = parse_config(
+            r#"
+[languages.py3]
+command = "python3"
+args = ["-"]
+print = 'print("{sentinel}", {expr})'
+"#,
+        )
+        .unwrap();
+        let options = LangOptions {
+            persistent: true,
+            ..LangOptions::default()
+        };
+        let lang = GenericLang::new(config.languages[0].clone(), options).unwrap();
+        let blocks = [
+            CodeBlock {
+                id: BlockId::new(0),
+                content: "import os",
+            },
+            CodeBlock {
+                id: BlockId::new(1),
+                content: "os.getpid() #=",
+            },
+        ];
+
+        let first = lang.evaluate(&blocks);
+        assert_eq!(first.len(), 1);
+        assert_eq!(lang.evaluate(&blocks)[0].content, first[0].content);
+    }
+
+    #[test]
+    fn assignment_regex_picks_the_variable() {
+        let lang = python_as_generic();
+        assert!(matches!(
+            lang.split("total = 1 + 2 #="),
+            CodeLine::EvalAssignment { var: "total", .. }
+        ));
+        assert!(matches!(lang.split("a == b #="), CodeLine::Eval { .. }));
+        assert_eq!(
+            lang.tangle_line("a + 1 #= 2", Markers::Print),
+            "print(\"\", a + 1)"
+        );
+    }
+
+    #[test]
+    fn configured_quotes_hide_markers() {
+        let spec = GenericSpec {
+            name: "pipes".into(),
+            strings: Some(vec!['|']),
+            ..GenericSpec::default()
+        };
+        let lang = GenericLang::new(spec, LangOptions::default()).unwrap();
+        assert!(matches!(lang.split("s = |a #= b|"), CodeLine::Code { .. }));
+        assert!(matches!(
+            lang.split("s = \"a #= b\""),
+            CodeLine::Eval { .. }
+        ));
+    }
+
+    #[test]
+    fn bad_regex_is_reported() {
+        let spec = GenericSpec {
+            name: "broken".into(),
+            assignment: Some("(".into()),
+            ..GenericSpec::default()
+        };
+        assert!(GenericLang::new(spec, LangOptions::default()).is_err());
+    }
+}
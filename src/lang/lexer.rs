@@ -0,0 +1,152 @@
+//! Just enough lexing to find eval markers and comments outside string
+//! literals: `s = "a #= b"` has no marker, and the `#` in a `'#ff0000'`
+//! result doesn't start a comment.
+
+use std::borrow::Cow;
+
+/// A kind of string literal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub open: Cow<'static, str>,
+    pub close: Cow<'static, str>,
+    /// Whether a backslash keeps the next character from closing it.
+    pub escapes: bool,
+}
+
+/// How a language writes string literals.
+#[derive(Debug, PartialEq)]
+pub struct Lexer {
+    /// Tried in order, so longer delimiters (`"""`) must come first.
+    pub quotes: Cow<'static, [Quote]>,
+    /// A quote right after a digit is a unit, as in Fend's `5'10"`.
+    pub primes: bool,
+}
+
+const fn quote(delimiter: &'static str, escapes: bool) -> Quote {
+    Quote {
+        open: Cow::Borrowed(delimiter),
+        close: Cow::Borrowed(delimiter),
+        escapes,
+    }
+}
+
+impl Lexer {
+    /// Double- and single-quoted strings with backslash escapes, and
+    /// backtick template literals, as in most C-like languages.
+    pub const DEFAULT: Lexer = Lexer {
+        quotes: Cow::Borrowed(&[quote("\"", true), quote("'", true), quote("`", true)]),
+        primes: false,
+    };
+
+    /// Prefixes need no handling: even in a raw string (`r"\"..."`) a
+    /// backslash keeps the quote after it from closing the literal.
+    pub const PYTHON: Lexer = Lexer {
+        quotes: Cow::Borrowed(&[
+            quote("\"\"\"", true),
+            quote("'''", true),
+            quote("\"", true),
+            quote("'", true),
+        ]),
+        primes: false,
+    };
+
+    pub const NUMBAT: Lexer = Lexer {
+        quotes: Cow::Borrowed(&[quote("\"", true)]),
+        primes: false,
+    };
+
+    pub const FEND: Lexer = Lexer {
+        quotes: Cow::Borrowed(&[quote("\"", true), quote("'", true)]),
+        primes: true,
+    };
+
+    /// Single-character quotes with backslash escapes, as a config
+    /// language or plugin lists them.
+    pub fn with_quotes(quotes: &[char]) -> Lexer {
+        let quotes = quotes.iter().map(|ch| Quote {
+            open: Cow::Owned(ch.to_string()),
+            close: Cow::Owned(ch.to_string()),
+            escapes: true,
+        });
+        Lexer {
+            quotes: Cow::Owned(quotes.collect()),
+            primes: false,
+        }
+    }
+
+    /// The byte offset of the first `needle` in `text` outside string
+    /// literals. With `stop`, the search ends at the first `stop` (a
+    /// comment token) that isn't part of a `needle`.
+    pub fn find(&self, text: &str, needle: &str, stop: Option<&str>) -> Option<usize> {
+        let mut idx = 0;
+        while let Some(ch) = text[idx..].chars().next() {
+            let rest = &text[idx..];
+            if rest.starts_with(needle) {
+                return Some(idx);
+            }
+            if stop.is_some_and(|stop| rest.starts_with(stop)) {
+                return None;
+            }
+            idx = self.literal_end(text, idx).unwrap_or(idx + ch.len_utf8());
+        }
+        None
+    }
+
+    /// Where the string literal opening at `start` ends. A quote that is
+    /// never closed on the line isn't treated as opening one.
+    fn literal_end(&self, text: &str, start: usize) -> Option<usize> {
+        if self.primes && text[..start].ends_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let rest = &text[start..];
+        let quote = self
+            .quotes
+            .iter()
+            .find(|quote| rest.starts_with(&*quote.open))?;
+        let body = start + quote.open.len();
+        let mut chars = text[body..].char_indices();
+        while let Some((offset, ch)) = chars.next() {
+            if text[body + offset..].starts_with(&*quote.close) {
+                return Some(body + offset + quote.close.len());
+            }
+            if quote.escapes && ch == '\\' {
+                chars.next();
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_string_literals() {
+        let lexer = Lexer::PYTHON;
+        assert_eq!(lexer.find("s = \"a #= b\"", "#=", Some("#")), None);
+        assert_eq!(lexer.find("s = \"a #= b\" #=", "#=", Some("#")), Some(13));
+        assert_eq!(lexer.find("s = 'it\\'s #=' #=", "#=", Some("#")), Some(15));
+        assert_eq!(
+            lexer.find("s = \"\"\"x\"#=\"\"\" #=", "#=", Some("#")),
+            Some(15)
+        );
+        assert_eq!(lexer.find(" '#ff0000' # red", "#", None), Some(11));
+        // An unclosed quote is just a character.
+        assert_eq!(lexer.find("don't #= x", "#=", Some("#")), Some(6));
+    }
+
+    #[test]
+    fn stops_at_comments() {
+        let lexer = Lexer::PYTHON;
+        assert_eq!(lexer.find("x  # not #= a marker", "#=", Some("#")), None);
+        assert_eq!(lexer.find("x # => 1", "# =>", Some("#")), Some(2));
+    }
+
+    #[test]
+    fn fend_primes_are_units() {
+        let line = "5'10\" to cm #= 177.8 cm # 'tall'";
+        assert_eq!(Lexer::FEND.find(line, "#=", Some("#")), Some(12));
+        assert_eq!(Lexer::FEND.find("\"#=\" #=", "#=", Some("#")), Some(5));
+    }
+}
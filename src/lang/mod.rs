@@ -1,18 +1,42 @@
 use std::fmt::Debug;
+use std::ops::Range;
 
 use crate::{
-    document::{CodeBlock, CodeBlockUpdate},
-    lang::{fend::FendLang, numbat::NumbatLang, python::PythonLang},
+    config::Config,
+    document::{CodeBlock, CodeBlockUpdate, Evaluation},
+    lang::{
+        fend::FendLang, generic::GenericLang, numbat::NumbatLang, plugin::PluginLang,
+        python::PythonLang, registry::LanguageInfo,
+    },
 };
 
+pub use lexer::Lexer;
+
 pub mod fend;
+pub mod generic;
+pub mod lexer;
 pub mod numbat;
+pub mod plugin;
 pub mod python;
+pub mod registry;
+pub mod runner;
 
 pub trait Language {
     fn name(&self) -> &str;
     fn eval_marker(&self) -> &str;
-    fn evaluate(&self, blocks: &[CodeBlock]) -> Vec<CodeBlockUpdate>;
+    /// The line comment token, which may follow a result (`#= 42 # note`).
+    fn comment(&self) -> &str;
+    /// Its string literals, inside which markers and comments don't count.
+    fn lexer(&self) -> &Lexer;
+    #[allow(dead_code)]
+    fn evaluate(&self, blocks: &[CodeBlock]) -> Vec<CodeBlockUpdate> {
+        self.evaluate_capturing(blocks, &[]).updates
+    }
+    /// Like `evaluate`, also returning what each `capture` range of blocks
+    /// printed and any errors.
+    fn evaluate_capturing(&self, blocks: &[CodeBlock], capture: &[Range<usize>]) -> Evaluation;
+    /// Rewrites one line for a standalone script (`equals tangle`).
+    fn tangle_line(&self, line: &str, markers: Markers) -> String;
 }
 
 impl Debug for dyn Language {
@@ -26,12 +50,138 @@ impl Debug for dyn Language {
     }
 }
 
-pub fn get_language_spec(lang_str: &str) -> Option<Box<dyn Language>> {
+/// Per-document settings that tweak how a language evaluates.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LangOptions {
+    /// Replaces the language's default eval marker.
+    pub marker: Option<String>,
+    /// Markers for single languages, by name, which win over `marker`.
+    pub language_markers: Vec<(String, String)>,
+    /// Rounds numeric results to at most this many decimal places.
+    pub precision: Option<usize>,
+    /// Keeps a warm interpreter between evaluations, for languages that
+    /// have one.
+    pub persistent: bool,
+}
+
+impl LangOptions {
+    /// The marker `language` uses: its own override, the document's, or
+    /// `default`.
+    pub fn marker_for<'a>(&'a self, language: &str, default: &'a str) -> &'a str {
+        self.language_markers
+            .iter()
+            .find(|(name, _)| name == language)
+            .map(|(_, marker)| marker.as_str())
+            .or(self.marker.as_deref())
+            .unwrap_or(default)
+    }
+
+    /// Applies the configured precision to the leading number of `value`,
+    /// keeping any unit or suffix that follows it (e.g. `3.28084 ft`).
+    pub fn format_result(&self, value: &str) -> String {
+        let Some(precision) = self.precision else {
+            return value.to_string();
+        };
+
+        let (number, rest) = value.split_at(value.find(char::is_whitespace).unwrap_or(value.len()));
+        if !number.contains('.') {
+            return value.to_string();
+        }
+        let Ok(parsed) = number.parse::<f64>() else {
+            return value.to_string();
+        };
+
+        let mut rounded = format!("{parsed:.precision$}");
+        if rounded.contains('.') {
+            rounded.truncate(rounded.trim_end_matches('0').trim_end_matches('.').len());
+        }
+        format!("{rounded}{rest}")
+    }
+}
+
+/// What `equals tangle` does with marked lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Markers {
+    /// Drop the marker and result, keeping the code.
+    #[default]
+    Strip,
+    /// Print the value the marker would have shown.
+    Print,
+    /// Check the value against the recorded result (printing it if none).
+    Assert,
+}
+
+/// Renders a parsed line for a standalone script, keeping the indentation
+/// of `line`. `print` and `assert` build the language's statements for an
+/// expression and, for `assert`, its recorded result.
+pub fn tangle_line(
+    line: &str,
+    parsed: &CodeLine,
+    markers: Markers,
+    print: impl Fn(&str) -> String,
+    assert: impl Fn(&str, &str) -> String,
+) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let (code, var, result, comment) = match parsed {
+        CodeLine::Code { code: "" } => return String::new(),
+        CodeLine::Code { code } => return format!("{indent}{code}"),
+        CodeLine::Eval {
+            code,
+            result,
+            comment,
+            ..
+        } => (*code, None, *result, *comment),
+        CodeLine::EvalAssignment {
+            var,
+            code,
+            result,
+            comment,
+            ..
+        } => (*code, Some(*var), *result, *comment),
+    };
+
+    let value = var.unwrap_or(code);
+    let mut statements = Vec::new();
+    if var.is_some() || markers == Markers::Strip {
+        statements.push(code.to_string());
+    }
+    match (markers, result) {
+        (Markers::Strip, _) => {}
+        (Markers::Assert, Some(expected)) => statements.push(assert(value, expected)),
+        _ => statements.push(print(value)),
+    }
+    if let (Some(comment), Some(last)) = (comment, statements.last_mut()) {
+        last.push(' ');
+        last.push_str(comment);
+    }
+
+    statements
+        .iter()
+        .map(|statement| format!("{indent}{statement}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Languages from `config` take precedence over the built-in ones, so a
+/// project can redefine how `python` runs.
+pub fn get_language_spec(
+    lang_str: &str,
+    options: &LangOptions,
+    config: &Config,
+) -> Option<Box<dyn Language>> {
+    if let Some(spec) = config.language(lang_str) {
+        let lang = GenericLang::new(spec.clone(), options.clone()).ok()?;
+        return Some(Box::new(lang));
+    }
+    if let Some(spec) = config.plugin(lang_str) {
+        return PluginLang::configured(spec, options)
+            .map(|lang| Box::new(lang) as Box<dyn Language>);
+    }
     match lang_str {
-        "python" => Some(Box::new(PythonLang::new())),
-        "numbat" => Some(Box::new(NumbatLang::new())),
-        "fend" => Some(Box::new(FendLang::new())),
-        _ => None,
+        "python" => Some(Box::new(PythonLang::with_options(options.clone()))),
+        "numbat" => Some(Box::new(NumbatLang::with_options(options.clone()))),
+        "fend" => Some(Box::new(FendLang::with_options(options.clone()))),
+        _ => PluginLang::find(lang_str, options).map(|lang| Box::new(lang) as Box<dyn Language>),
     }
 }
 
@@ -92,15 +242,38 @@ impl<'a> CodeLine<'a> {
     }
 }
 
-/// Split a Python line into structured form.
+/// The `#<name>=` markers in `line` (`#nb=`, `#fd=`), each with the name
+/// it carries. Polyglot files use them to send one line to a language.
+pub fn marker_variants(line: &str) -> impl Iterator<Item = (&str, &str)> {
+    line.match_indices('#').filter_map(move |(start, _)| {
+        let rest = &line[start + 1..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        (len > 0 && rest[len..].starts_with('='))
+            .then(|| (&rest[..len], &line[start..start + len + 2]))
+    })
+}
+
+/// The marker `line` is evaluated with: a variant naming `language`, if
+/// it has one, or else `marker`.
+pub fn line_marker<'a>(line: &'a str, marker: &'a str, language: &LanguageInfo) -> &'a str {
+    marker_variants(line)
+        .find(|(name, _)| language.is_called(name))
+        .map_or(marker, |(_, variant)| variant)
+}
+
+/// Split a line into structured form.
 /// - `input`: line to parse
 /// - `marker`: eval marker (e.g. "#=")
 /// - `comment`: comment symbol (e.g. "#")
+/// - `lexer`: the language's string literals, inside which neither counts
 /// - `extract_assignment`: function that returns Some(var_name) if line is assignment
 pub fn split_line<'a, F>(
     input: &'a str,
     marker: &'a str,
     comment: &str,
+    lexer: &Lexer,
     extract_assignment: F,
 ) -> CodeLine<'a>
 where
@@ -108,18 +281,19 @@ where
 {
     let trimmed = input.trim();
 
-    // 🟢 Case 1: eval line (contains marker)
-    if let Some(marker_pos) = trimmed.find(marker) {
+    // 🟢 Case 1: eval line (contains marker before any comment)
+    if let Some(marker_pos) = lexer.find(trimmed, marker, Some(comment)) {
         let (before_marker, after_marker) = trimmed.split_at(marker_pos);
         let after_marker = &after_marker[marker.len()..];
 
         // Split into result and trailing comment
-        let (result_part, comment_part) = if let Some(cpos) = after_marker.find(comment) {
-            let (res, com) = after_marker.split_at(cpos);
-            (res.trim(), Some(com))
-        } else {
-            (after_marker.trim(), None)
-        };
+        let (result_part, comment_part) =
+            if let Some(cpos) = trailing_comment(after_marker, comment, lexer) {
+                let (res, com) = after_marker.split_at(cpos);
+                (res.trim(), Some(com))
+            } else {
+                (after_marker.trim(), None)
+            };
 
         let result = if result_part.is_empty() {
             None
@@ -148,10 +322,110 @@ where
     }
 }
 
+/// Where the comment after a result starts: at a comment token preceded by
+/// whitespace (`#= 6 #note`). Right after the marker, the token starts an
+/// unquoted result (`#= #ff0000`) unless whitespace follows it.
+fn trailing_comment(result: &str, comment: &str, lexer: &Lexer) -> Option<usize> {
+    let start = result.len() - result.trim_start().len();
+    let mut from = 0;
+    while let Some(pos) = lexer.find(&result[from..], comment, None) {
+        let at = from + pos;
+        let after = &result[at + comment.len()..];
+        let is_comment = if at == start {
+            after.chars().next().is_none_or(char::is_whitespace)
+        } else {
+            result[..at].ends_with(char::is_whitespace)
+        };
+        if is_comment {
+            return Some(at);
+        }
+        from = at + comment.len();
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn markers_and_comments_inside_strings_are_ignored() {
+        let split = |line| split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
+
+        assert_eq!(
+            split("s = \"a #= b\""),
+            CodeLine::Code {
+                code: "s = \"a #= b\""
+            }
+        );
+        assert_eq!(
+            split("color #= '#ff0000' # red"),
+            CodeLine::Eval {
+                code: "color",
+                marker: "#=",
+                result: Some("'#ff0000'"),
+                comment: Some("# red"),
+            }
+        );
+        assert!(matches!(
+            split("color #= #ff0000 # red"),
+            CodeLine::Eval {
+                result: Some("#ff0000"),
+                comment: Some("# red"),
+                ..
+            }
+        ));
+        assert!(matches!(
+            split("b + 2 #= 6 #note"),
+            CodeLine::Eval {
+                result: Some("6"),
+                comment: Some("#note"),
+                ..
+            }
+        ));
+        assert!(matches!(
+            split("b + 2 #= # note"),
+            CodeLine::Eval {
+                result: None,
+                comment: Some("# note"),
+                ..
+            }
+        ));
+        assert_eq!(
+            split("x # not #= evaluated"),
+            CodeLine::Code {
+                code: "x # not #= evaluated"
+            }
+        );
+    }
+
+    #[test]
+    fn marker_variants_name_their_language() {
+        let line = "x -> cm #nb= 200 cm # note";
+        assert_eq!(
+            marker_variants(line).collect::<Vec<_>>(),
+            vec![("nb", "#nb=")]
+        );
+        assert_eq!(line_marker(line, "#=", &numbat::INFO), "#nb=");
+        assert_eq!(line_marker(line, "#=", &fend::INFO), "#=");
+        assert_eq!(marker_variants("x #= 1 #").count(), 0);
+        assert!(matches!(
+            split_line(
+                line,
+                line_marker(line, "#=", &numbat::INFO),
+                "#",
+                &Lexer::NUMBAT,
+                |_| None
+            ),
+            CodeLine::Eval {
+                code: "x -> cm",
+                marker: "#nb=",
+                result: Some("200 cm"),
+                ..
+            }
+        ));
+    }
+
     /// Very simple assignment detector for tests.
     /// Returns the variable name before '=' if one exists.
     /// Ignores spacing and does no syntax validation.
@@ -171,7 +445,7 @@ mod tests {
     #[test]
     fn test_normal_code() {
         let line = "a = 1 + b # comment";
-        let result = split_line(line, "#=", "#", extract_assignment_var);
+        let result = split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
 
         assert_eq!(
             result,
@@ -184,7 +458,7 @@ mod tests {
     #[test]
     fn test_eval() {
         let line = "b + 2 #= 6 # comment";
-        let result = split_line(line, "#=", "#", extract_assignment_var);
+        let result = split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
 
         assert_eq!(
             result,
@@ -200,7 +474,7 @@ mod tests {
     #[test]
     fn test_eval_no_result() {
         let line = "b + 2 #= # comment";
-        let result = split_line(line, "#=", "#", extract_assignment_var);
+        let result = split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
 
         assert_eq!(
             result,
@@ -216,7 +490,7 @@ mod tests {
     #[test]
     fn test_eval_assignment() {
         let line = "c = b + 2 #= 6 # comment";
-        let result = split_line(line, "#=", "#", extract_assignment_var);
+        let result = split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
 
         assert_eq!(
             result,
@@ -233,7 +507,7 @@ mod tests {
     #[test]
     fn test_eval_assignment_no_comment() {
         let line = "x = y + 3 #= 10";
-        let result = split_line(line, "#=", "#", extract_assignment_var);
+        let result = split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
 
         assert_eq!(
             result,
@@ -250,7 +524,7 @@ mod tests {
     #[test]
     fn test_eval_with_spaces() {
         let line = "  d + 4   #=    12    # some note   ";
-        let result = split_line(line, "#=", "#", extract_assignment_var);
+        let result = split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
 
         assert_eq!(
             result,
@@ -263,10 +537,62 @@ mod tests {
         );
     }
 
+    #[test]
+    fn language_markers_win_over_the_document_marker() {
+        let options = LangOptions {
+            marker: Some("=>".into()),
+            language_markers: vec![("numbat".into(), "%=".into())],
+            ..LangOptions::default()
+        };
+
+        assert_eq!(options.marker_for("numbat", "#="), "%=");
+        assert_eq!(options.marker_for("python", "#="), "=>");
+        assert_eq!(LangOptions::default().marker_for("python", "#="), "#=");
+    }
+
+    #[test]
+    fn format_result_rounds_leading_number() {
+        let options = LangOptions {
+            precision: Some(2),
+            ..LangOptions::default()
+        };
+
+        assert_eq!(options.format_result("3.14159"), "3.14");
+        assert_eq!(options.format_result("91.44 cm"), "91.44 cm");
+        assert_eq!(options.format_result("2.50001 m/s"), "2.5 m/s");
+        assert_eq!(options.format_result("42"), "42");
+        assert_eq!(options.format_result("'hello.world'"), "'hello.world'");
+        assert_eq!(LangOptions::default().format_result("3.14159"), "3.14159");
+    }
+
+    #[test]
+    fn tangle_line_rewrites_markers() {
+        let print = |code: &str| format!("print({code})");
+        let assert = |code: &str, expected: &str| format!("assert {code} == {expected}");
+        let tangle = |line: &str, markers| {
+            let parsed = split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
+            tangle_line(line, &parsed, markers, print, assert)
+        };
+
+        assert_eq!(
+            tangle("    b + 2 #= 6 # note", Markers::Strip),
+            "    b + 2 # note"
+        );
+        assert_eq!(tangle("b + 2 #= 6", Markers::Print), "print(b + 2)");
+        assert_eq!(tangle("b + 2 #= 6", Markers::Assert), "assert b + 2 == 6");
+        assert_eq!(tangle("b + 2 #=", Markers::Assert), "print(b + 2)");
+        assert_eq!(
+            tangle("  c = b + 2 #= 6", Markers::Assert),
+            "  c = b + 2\n  assert c == 6"
+        );
+        assert_eq!(tangle("x = 1", Markers::Print), "x = 1");
+        assert_eq!(tangle("   ", Markers::Print), "");
+    }
+
     #[test]
     fn test_empty_line() {
         let line = "";
-        let result = split_line(line, "#=", "#", extract_assignment_var);
+        let result = split_line(line, "#=", "#", &Lexer::PYTHON, extract_assignment_var);
         assert_eq!(result, CodeLine::Code { code: "" });
     }
 }
@@ -1,127 +1,173 @@
-use std::process::{Command, Stdio};
+use std::ops::Range;
+use std::process::Command;
 
 use crate::{
-    document::{CodeBlock, CodeBlockUpdate},
-    lang::{CodeLine, Language, split_line},
+    document::{CodeBlock, Evaluation},
+    lang::{
+        CodeLine, LangOptions, Language, Lexer, Markers, line_marker,
+        registry::LanguageInfo,
+        runner::{self, Channel, Execution, Protocol, ScriptRunner, Warm},
+        split_line, tangle_line,
+    },
 };
 
-const MARKER: &str = "#=";
-const COMMENT: &str = "#";
+pub const INFO: LanguageInfo = LanguageInfo {
+    name: "numbat",
+    aliases: &["nbt", "nb"],
+    extensions: &["nbt", "nb"],
+    fences: &[],
+    marker: "#=",
+    comment: "#",
+    lexer: &Lexer::NUMBAT,
+};
 
-pub struct NumbatLang;
+pub struct NumbatLang {
+    options: LangOptions,
+    marker: String,
+    comment: String,
+    warm: Option<Warm>,
+}
 
 impl NumbatLang {
-    pub fn new() -> Self {
-        Self
-    }
-
-    fn evaluate_in_place(&self, blocks: &mut [String]) {
-        let originals: Vec<String> = blocks.iter().cloned().collect();
-        let parsed: Vec<_> = originals
-            .iter()
-            .map(|line| split_line(line, MARKER, COMMENT, extract_assigned_var))
-            .collect();
-
-        let has_eval = parsed.iter().any(|line| {
-            matches!(
-                line,
-                CodeLine::Eval { .. } | CodeLine::EvalAssignment { .. }
-            )
-        });
-        if !has_eval {
-            return;
-        }
-
-        let script = build_numbat_expressions(&parsed);
-        if script.is_empty() {
-            return;
+    pub fn with_options(options: LangOptions) -> Self {
+        Self {
+            marker: options.marker_for(INFO.name, INFO.marker).to_string(),
+            comment: INFO.comment.to_string(),
+            warm: options.persistent.then(Warm::default),
+            options,
         }
+    }
+}
 
-        if let Some(output) = run_numbat(&script) {
-            for line in output.lines() {
-                if let Some(rest) = line.strip_prefix("##RESULT:") {
-                    let mut parts = rest.trim_start().splitn(2, ' ');
-                    let idx_str = parts.next().unwrap_or_default();
-                    let value = parts.next().unwrap_or("").trim();
-
-                    if let Ok(idx) = idx_str.parse::<usize>() {
-                        if let Some(target) = blocks.get_mut(idx) {
-                            let reconstructed = parsed[idx].reconstruct(value);
-                            *target = reconstructed;
-                        }
-                    }
-                }
-            }
-        }
+impl Default for NumbatLang {
+    fn default() -> Self {
+        Self::with_options(LangOptions::default())
     }
 }
 
 impl Language for NumbatLang {
     fn name(&self) -> &str {
-        "numbat"
+        INFO.name
     }
 
     fn eval_marker(&self) -> &str {
-        MARKER
+        &self.marker
     }
 
-    fn evaluate(&self, blocks: &[CodeBlock]) -> Vec<CodeBlockUpdate> {
-        if blocks.is_empty() {
-            return Vec::new();
-        }
+    fn comment(&self) -> &str {
+        &self.comment
+    }
 
-        let mut working: Vec<String> = blocks.iter().map(|b| b.content.to_string()).collect();
-        self.evaluate_in_place(&mut working);
+    fn lexer(&self) -> &Lexer {
+        INFO.lexer
+    }
 
-        blocks
-            .iter()
-            .zip(working.into_iter())
-            .filter_map(|(block, new_content)| {
-                if block.content == new_content {
-                    None
-                } else {
-                    Some(CodeBlockUpdate {
-                        id: block.id,
-                        content: new_content,
-                    })
-                }
-            })
-            .collect()
+    fn evaluate_capturing(&self, blocks: &[CodeBlock], capture: &[Range<usize>]) -> Evaluation {
+        runner::evaluate(self, blocks, capture)
+    }
+
+    fn tangle_line(&self, line: &str, markers: Markers) -> String {
+        let parsed = self.split(line);
+        tangle_line(
+            line,
+            &parsed,
+            markers,
+            |code| format!("print({code})"),
+            |code, expected| format!("assert_eq({code}, {expected})"),
+        )
     }
 }
 
-fn run_numbat(expressions: &[String]) -> Option<String> {
-    if expressions.is_empty() {
-        return None;
+impl ScriptRunner for NumbatLang {
+    fn options(&self) -> &LangOptions {
+        &self.options
     }
 
-    let mut command = Command::new("numbat");
-    command
-        .arg("--no-config")
-        .arg("--no-init")
-        .arg("--color")
-        .arg("never");
+    fn split<'a>(&'a self, line: &'a str) -> CodeLine<'a> {
+        let marker = line_marker(line, self.eval_marker(), &INFO);
+        split_line(
+            line,
+            marker,
+            self.comment(),
+            INFO.lexer,
+            extract_assigned_var,
+        )
+    }
 
-    for expr in expressions {
-        command.arg("--expression").arg(expr);
+    fn fragments(
+        &self,
+        lines: &[CodeLine],
+        _originals: &[&str],
+        capture: &[Range<usize>],
+        protocol: &Protocol,
+    ) -> Vec<String> {
+        build_numbat_expressions(lines, capture, protocol)
     }
 
-    let output = command
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .ok()?;
+    /// One expression per line, passed as separate `--expression`s.
+    fn program(&self, fragments: &[String]) -> String {
+        fragments
+            .iter()
+            .filter(|fragment| !fragment.is_empty())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 
-    if !output.status.success() {
-        return None;
+    fn run(&self, program: &str) -> Option<Execution> {
+        if program.is_empty() {
+            return None;
+        }
+
+        let mut command = Command::new("numbat");
+        command
+            .arg("--no-config")
+            .arg("--no-init")
+            .arg("--color")
+            .arg("never");
+        for expr in program.lines() {
+            command.arg("--expression").arg(expr);
+        }
+        Some(runner::run_reporting(&mut command, None))
     }
 
-    Some(String::from_utf8_lossy(&output.stdout).to_string())
+    fn warm(&self) -> Option<&Warm> {
+        self.warm.as_ref()
+    }
+
+    /// Numbat's REPL reads statements from stdin line by line.
+    fn repl_command(&self, _protocol: &Protocol) -> Option<Command> {
+        let mut command = Command::new("numbat");
+        command
+            .arg("--no-config")
+            .arg("--no-init")
+            .arg("--color")
+            .arg("never");
+        Some(command)
+    }
+
+    /// The chunk's statements, then one printing the `done` record.
+    fn repl_chunk(&self, chunk: &str, protocol: &Protocol) -> String {
+        format!("{chunk}\nprint(\"{}\")\n", protocol.tag(Channel::Done, 0))
+    }
 }
 
-fn build_numbat_expressions(lines: &[CodeLine]) -> Vec<String> {
-    let mut expressions = Vec::new();
+/// The expressions of each line, joined by newlines. Numbat values print
+/// on one line, so results are interpolated into the record unescaped.
+fn build_numbat_expressions(
+    lines: &[CodeLine],
+    capture: &[Range<usize>],
+    protocol: &Protocol,
+) -> Vec<String> {
+    let mut fragments = Vec::new();
     for (idx, line) in lines.iter().enumerate() {
+        let mut expressions = Vec::new();
+        if let Some(group) = capture.iter().position(|range| range.start == idx) {
+            expressions.push(format!(
+                "print(\"{}\")",
+                protocol.tag(Channel::Begin, group)
+            ));
+        }
         match line {
             CodeLine::Code { code } => {
                 if !code.is_empty() {
@@ -130,23 +176,31 @@ fn build_numbat_expressions(lines: &[CodeLine]) -> Vec<String> {
             }
             CodeLine::Eval { code, .. } => {
                 if !code.is_empty() {
-                    expressions.push(render_print(idx, code));
+                    expressions.push(render_print(protocol, idx, code));
                 }
             }
             CodeLine::EvalAssignment { code, var, .. } => {
                 if !code.is_empty() {
                     expressions.push((*code).to_string());
                 }
-                expressions.push(render_print(idx, var));
+                expressions.push(render_print(protocol, idx, var));
             }
         }
+        if capture.iter().any(|range| range.end == idx + 1) {
+            expressions.push(format!("print(\"{}\")", protocol.tag(Channel::End, 0)));
+        }
+        fragments.push(expressions.join("\n"));
     }
-    expressions
+    fragments
 }
 
-fn render_print(idx: usize, expr: &str) -> String {
+fn render_print(protocol: &Protocol, idx: usize, expr: &str) -> String {
     let escaped = escape_quotes(expr);
-    format!("print(\"##RESULT:{} {{{}}}\")", idx, escaped)
+    format!(
+        "print(\"{}{{{}}}\")",
+        protocol.tag(Channel::Result, idx),
+        escaped
+    )
 }
 
 fn escape_quotes(expr: &str) -> String {
@@ -173,8 +227,8 @@ mod tests {
     use crate::document::{BlockId, CodeBlock};
 
     fn eval_blocks(blocks: &mut [String]) {
-        let lang = NumbatLang;
-        let snapshots: Vec<String> = blocks.iter().cloned().collect();
+        let lang = NumbatLang::default();
+        let snapshots: Vec<String> = blocks.to_vec();
         let code_blocks: Vec<CodeBlock> = snapshots
             .iter()
             .enumerate()
@@ -0,0 +1,360 @@
+//! Out-of-tree languages: an `equals-lang-<name>` executable on `PATH` (or
+//! in a directory listed in `EQUALS_PLUGIN_PATH`), or one declared in a
+//! `[plugins.<name>]` table of `equals.toml`, that speaks JSON over stdio.
+//!
+//! equals writes one request object to the plugin's stdin and closes it:
+//!
+//! ```json
+//! {"version": 1, "language": "dsl", "marker": "#=",
+//!  "lines": [{"text": "x = 2", "code": "x = 2", "eval": false},
+//!            {"text": "x * 3 #=", "code": "x * 3", "eval": true}],
+//!  "capture": [[0, 2]]}
+//! ```
+//!
+//! `code` is the line without its marker, result and comment. The plugin
+//! answers with one object on stdout; every field is optional:
+//!
+//! ```json
+//! {"results": [{"line": 1, "value": "6"}],
+//!  "errors": [{"line": 0, "message": "unknown name"}],
+//!  "outputs": ["printed by lines 0-1\n"]}
+//! ```
+//!
+//! `line` indexes `lines` and `outputs` follows `capture`. Anything the
+//! plugin writes to stderr is reported as an error.
+//!
+//! Before its first evaluation, equals asks the plugin for the comment token
+//! of its language with `{"version": 1, "describe": true}`; a plugin that
+//! answers `{"comment": "--"}` gets results followed by `-- note` comments
+//! split off. Any other answer keeps `#`, as does a `comment` in its config
+//! table, which wins.
+
+use std::env;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{
+    config::PluginSpec,
+    document::{CodeBlock, Evaluation},
+    json::{self, Json, encode_string},
+    lang::{
+        CodeLine, LangOptions, Language, Lexer, Markers,
+        runner::{self, Report},
+        split_line, tangle_line,
+    },
+};
+
+const MARKER: &str = "#=";
+const COMMENT: &str = "#";
+const PROTOCOL_VERSION: usize = 1;
+
+/// Plugin executables are named `equals-lang-<name>`.
+pub const PLUGIN_PREFIX: &str = "equals-lang-";
+
+pub struct PluginLang {
+    name: String,
+    program: PathBuf,
+    options: LangOptions,
+    marker: String,
+    comment: String,
+    lexer: Lexer,
+}
+
+impl PluginLang {
+    /// Finds the `equals-lang-<name>` executable, if one is installed.
+    pub fn find(name: &str, options: &LangOptions) -> Option<Self> {
+        let program = find_program(name)?;
+        Some(Self {
+            comment: describe(&program).unwrap_or_else(|| COMMENT.to_string()),
+            ..Self::new(name, &program, options.clone())
+        })
+    }
+
+    /// The plugin a config table declares, taking its comment token, marker
+    /// and string quotes from there (or the comment token from the plugin).
+    /// `None` if
+    /// its executable can't be found.
+    pub fn configured(spec: &PluginSpec, options: &LangOptions) -> Option<Self> {
+        let program = spec.path.clone().or_else(|| find_program(&spec.name))?;
+        let default_marker = spec.marker.as_deref().unwrap_or(MARKER);
+        Some(Self {
+            marker: options.marker_for(&spec.name, default_marker).to_string(),
+            comment: spec
+                .comment
+                .clone()
+                .or_else(|| describe(&program))
+                .unwrap_or_else(|| COMMENT.to_string()),
+            lexer: spec
+                .strings
+                .as_deref()
+                .map_or(Lexer::DEFAULT, Lexer::with_quotes),
+            ..Self::new(&spec.name, &program, options.clone())
+        })
+    }
+
+    pub fn new(name: &str, program: &Path, options: LangOptions) -> Self {
+        Self {
+            name: name.to_string(),
+            program: program.to_path_buf(),
+            marker: options.marker_for(name, MARKER).to_string(),
+            comment: COMMENT.to_string(),
+            lexer: Lexer::DEFAULT,
+            options,
+        }
+    }
+
+    fn split<'a>(&'a self, line: &'a str) -> CodeLine<'a> {
+        split_line(
+            line,
+            self.eval_marker(),
+            self.comment(),
+            &self.lexer,
+            |_| None,
+        )
+    }
+
+    fn request(
+        &self,
+        blocks: &[CodeBlock],
+        lines: &[CodeLine],
+        capture: &[Range<usize>],
+    ) -> String {
+        let lines = blocks
+            .iter()
+            .zip(lines)
+            .map(|(block, line)| {
+                let (code, eval) = match line {
+                    CodeLine::Code { code } => (*code, false),
+                    CodeLine::Eval { code, .. } | CodeLine::EvalAssignment { code, .. } => {
+                        (*code, true)
+                    }
+                };
+                format!(
+                    "{{\"text\": {}, \"code\": {}, \"eval\": {eval}}}",
+                    encode_string(block.content),
+                    encode_string(code)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let capture = capture
+            .iter()
+            .map(|range| format!("[{}, {}]", range.start, range.end))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{{\"version\": {PROTOCOL_VERSION}, \"language\": {}, \"marker\": {}, \"lines\": [{lines}], \"capture\": [{capture}]}}\n",
+            encode_string(&self.name),
+            encode_string(self.eval_marker())
+        )
+    }
+}
+
+impl Language for PluginLang {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn eval_marker(&self) -> &str {
+        &self.marker
+    }
+
+    fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    fn lexer(&self) -> &Lexer {
+        &self.lexer
+    }
+
+    fn evaluate_capturing(&self, blocks: &[CodeBlock], capture: &[Range<usize>]) -> Evaluation {
+        let lines: Vec<CodeLine> = blocks
+            .iter()
+            .map(|block| self.split(block.content))
+            .collect();
+        let request = self.request(blocks, &lines, capture);
+
+        let execution = match runner::run(&mut Command::new(&self.program), Some(&request)) {
+            Ok(execution) => execution,
+            Err(err) => {
+                return Evaluation {
+                    outputs: vec![String::new(); capture.len()],
+                    errors: vec![format!("{}: {err}", self.program.display())],
+                    ..Evaluation::default()
+                };
+            }
+        };
+        let mut report = match parse_response(&execution.stdout) {
+            Ok(report) => report,
+            Err(err) => Report {
+                failures: vec![format!("bad plugin response: {err}")],
+                ..Report::default()
+            },
+        };
+        report.outputs.resize(capture.len(), String::new());
+        runner::apply_report(&self.options, blocks, &lines, report, &execution.stderr)
+    }
+
+    /// equals doesn't know the plugin's print syntax, so markers are only
+    /// stripped.
+    fn tangle_line(&self, line: &str, _markers: Markers) -> String {
+        tangle_line(
+            line,
+            &self.split(line),
+            Markers::Strip,
+            str::to_string,
+            |code, _| code.to_string(),
+        )
+    }
+}
+
+/// Asks the plugin at `program` for its comment token.
+fn describe(program: &Path) -> Option<String> {
+    let request = format!("{{\"version\": {PROTOCOL_VERSION}, \"describe\": true}}\n");
+    let execution = runner::run(&mut Command::new(program), Some(&request)).ok()?;
+    let root = json::parse(execution.stdout.trim()).ok()?;
+    Some(root.get("comment")?.as_str()?.to_string())
+}
+
+/// The `equals-lang-<name>` executable in `EQUALS_PLUGIN_PATH` or on
+/// `PATH`.
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let file = format!("{PLUGIN_PREFIX}{name}");
+    let dirs = [env::var_os("EQUALS_PLUGIN_PATH"), env::var_os("PATH")];
+    dirs.iter()
+        .flatten()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(&file))
+        .find(|path| path.is_file())
+}
+
+fn parse_response(stdout: &str) -> Result<Report, String> {
+    let root = json::parse(stdout.trim())?;
+    let entries = |key: &str, field: &str| -> Vec<(usize, String)> {
+        root.get(key)
+            .map(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|entry| {
+                let line = entry.get("line")?.as_usize()?;
+                let text = entry.get(field)?.as_str()?;
+                Some((line, text.to_string()))
+            })
+            .collect()
+    };
+
+    Ok(Report {
+        results: entries("results", "value"),
+        errors: entries("errors", "message"),
+        failures: Vec::new(),
+        outputs: root
+            .get("outputs")
+            .map(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(|output| output.as_str().unwrap_or_default().to_string())
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::BlockId;
+
+    #[test]
+    fn request_carries_lines_and_marker_metadata() {
+        let lang = PluginLang::new("dsl", Path::new("equals-lang-dsl"), LangOptions::default());
+        let contents = ["say \"hi\"", "  x * 3 #= 5 # note"];
+        let blocks: Vec<CodeBlock> = contents
+            .iter()
+            .enumerate()
+            .map(|(idx, content)| CodeBlock {
+                id: BlockId::new(idx),
+                content,
+            })
+            .collect();
+        let lines: Vec<CodeLine> = contents.iter().map(|line| lang.split(line)).collect();
+
+        assert_eq!(
+            lang.request(&blocks, &lines, std::slice::from_ref(&(0..2))),
+            "{\"version\": 1, \"language\": \"dsl\", \"marker\": \"#=\", \"lines\": [\
+             {\"text\": \"say \\\"hi\\\"\", \"code\": \"say \\\"hi\\\"\", \"eval\": false}, \
+             {\"text\": \"  x * 3 #= 5 # note\", \"code\": \"x * 3\", \"eval\": true}], \
+             \"capture\": [[0, 2]]}\n"
+        );
+    }
+
+    #[test]
+    fn configured_plugins_use_their_comment_token() {
+        let spec = PluginSpec {
+            name: "dsl".into(),
+            path: Some(PathBuf::from("tools/dsl-equals")),
+            comment: Some("--".into()),
+            marker: Some("--=".into()),
+            ..PluginSpec::default()
+        };
+        let lang = PluginLang::configured(&spec, &LangOptions::default()).unwrap();
+        assert_eq!(lang.program, PathBuf::from("tools/dsl-equals"));
+        assert_eq!(lang.comment(), "--");
+        assert!(matches!(
+            lang.split("x * 3 --= 6 -- note"),
+            CodeLine::Eval {
+                code: "x * 3",
+                result: Some("6"),
+                comment: Some("-- note"),
+                ..
+            }
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugins_may_describe_their_comment_token() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("equals-plugin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("equals-lang-dsl");
+        std::fs::write(
+            &program,
+            "#!/bin/sh\ncat >/dev/null\necho '{\"comment\": \"--\"}'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let spec = PluginSpec {
+            name: "dsl".into(),
+            path: Some(program.clone()),
+            ..PluginSpec::default()
+        };
+        let described = PluginLang::configured(&spec, &LangOptions::default()).unwrap();
+        let configured = PluginLang::configured(
+            &PluginSpec {
+                comment: Some("//".into()),
+                ..spec
+            },
+            &LangOptions::default(),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(described.comment(), "--");
+        assert_eq!(configured.comment(), "//");
+    }
+
+    #[test]
+    fn response_fields_are_optional() {
+        let report = parse_response(
+            "{\"results\": [{\"line\": 1, \"value\": \"6\"}], \"errors\": [{\"line\": 0, \"message\": \"boom\"}]}\n",
+        )
+        .unwrap();
+        assert_eq!(report.results, vec![(1, "6".to_string())]);
+        assert_eq!(report.errors, vec![(0, "boom".to_string())]);
+        assert!(report.outputs.is_empty());
+
+        assert_eq!(parse_response("{}").unwrap(), Report::default());
+        assert!(parse_response("not json").is_err());
+    }
+}
@@ -1,82 +1,166 @@
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::ops::Range;
+use std::process::Command;
 
 use crate::{
-    document::{CodeBlock, CodeBlockUpdate},
-    lang::{CodeLine, Language, split_line},
+    document::{CodeBlock, Evaluation},
+    lang::{
+        CodeLine, LangOptions, Language, Lexer, Markers, line_marker,
+        registry::LanguageInfo,
+        runner::{self, Channel, Execution, Protocol, ScriptRunner, Warm},
+        split_line, tangle_line,
+    },
 };
 
-pub struct PythonLang;
+pub struct PythonLang {
+    options: LangOptions,
+    marker: String,
+    comment: String,
+    warm: Option<Warm>,
+}
+
+pub const INFO: LanguageInfo = LanguageInfo {
+    name: "python",
+    aliases: &["python3", "py"],
+    extensions: &["py", "pyw"],
+    fences: &[],
+    marker: "#=",
+    comment: "#",
+    lexer: &Lexer::PYTHON,
+};
 
-const MARKER: &str = "#=";
-const COMMENT: &str = "#";
+/// Defines `_equals_emit`, which writes a protocol record from its tag and
+/// payload.
+const PRELUDE: &str = r#"import sys as _equals_sys
+def _equals_emit(tag, value):
+    text = str(value).replace('\\', '\\\\').replace('\n', '\\n').replace('\r', '\\r')
+    _equals_sys.stdout.write(tag + text + '\n')"#;
+
+/// Runs chunks in one namespace until stdin closes. User code reads an
+/// empty stdin, as it would in a one-shot run.
+const DRIVER: &str = r#"
+import os as _equals_os, traceback as _equals_traceback
+_equals_chunks = _equals_sys.stdin.buffer
+_equals_sys.stdin = open(_equals_os.devnull)
+_equals_done, _equals_failure = _equals_sys.argv[1:3]
+_equals_namespace = {'__name__': '__main__', '_equals_emit': _equals_emit}
+while True:
+    _equals_header = _equals_chunks.readline()
+    if not _equals_header:
+        break
+    _equals_source = _equals_chunks.read(int(_equals_header)).decode()
+    try:
+        exec(compile(_equals_source, '<equals>', 'exec'), _equals_namespace)
+    except BaseException:
+        _equals_emit(_equals_failure, _equals_traceback.format_exc())
+    _equals_emit(_equals_done, '')
+    _equals_sys.stdout.flush()
+"#;
 
 impl PythonLang {
-    pub fn new() -> Self {
-        Self
+    pub fn with_options(options: LangOptions) -> Self {
+        Self {
+            marker: options.marker_for(INFO.name, INFO.marker).to_string(),
+            comment: INFO.comment.to_string(),
+            warm: options.persistent.then(Warm::default),
+            options,
+        }
+    }
+}
+
+impl Default for PythonLang {
+    fn default() -> Self {
+        Self::with_options(LangOptions::default())
     }
 }
 
 impl Language for PythonLang {
     fn name(&self) -> &str {
-        "python"
+        INFO.name
     }
 
     fn eval_marker(&self) -> &str {
-        MARKER
+        &self.marker
     }
 
-    fn evaluate(&self, blocks: &[CodeBlock]) -> Vec<CodeBlockUpdate> {
-        if blocks.is_empty() {
-            return Vec::new();
-        }
+    fn comment(&self) -> &str {
+        &self.comment
+    }
 
-        let mut working: Vec<String> = blocks.iter().map(|b| b.content.to_string()).collect();
-        self.evaluate_in_place(&mut working);
+    fn lexer(&self) -> &Lexer {
+        INFO.lexer
+    }
 
-        blocks
-            .iter()
-            .zip(working.into_iter())
-            .filter_map(|(block, new_content)| {
-                if block.content == new_content {
-                    None
-                } else {
-                    Some(CodeBlockUpdate {
-                        id: block.id,
-                        content: new_content,
-                    })
-                }
-            })
-            .collect()
+    fn evaluate_capturing(&self, blocks: &[CodeBlock], capture: &[Range<usize>]) -> Evaluation {
+        runner::evaluate(self, blocks, capture)
+    }
+
+    fn tangle_line(&self, line: &str, markers: Markers) -> String {
+        let parsed = self.split(line);
+        tangle_line(
+            line,
+            &parsed,
+            markers,
+            |code| format!("print({code})"),
+            |code, expected| format!("assert str({code}) == {}", python_string(expected)),
+        )
     }
 }
 
-impl PythonLang {
-    fn evaluate_in_place(&self, input: &mut [String]) {
-        let cloned_inputs: Vec<String> = input.to_vec();
+impl ScriptRunner for PythonLang {
+    fn options(&self) -> &LangOptions {
+        &self.options
+    }
 
-        let lines: Vec<_> = cloned_inputs
-            .iter()
-            .map(|s| split_line(s, self.eval_marker(), COMMENT, extract_assigned_var))
-            .collect();
+    fn split<'a>(&'a self, line: &'a str) -> CodeLine<'a> {
+        let marker = line_marker(line, self.eval_marker(), &INFO);
+        split_line(
+            line,
+            marker,
+            self.comment(),
+            INFO.lexer,
+            extract_assigned_var,
+        )
+    }
 
-        let script = build_python_script(&lines);
-        let output = run_python(&script);
-
-        for line in output.lines() {
-            if let Some(rest) = line.strip_prefix("##RESULT:") {
-                let mut parts = rest.split_whitespace();
-                if let Some(idx_str) = parts.next() {
-                    if let Ok(idx) = idx_str.parse::<usize>() {
-                        let value = parts.collect::<Vec<_>>().join(" ");
-                        if let Some(s) = input.get_mut(idx) {
-                            let new_line = lines[idx].reconstruct(&value);
-                            *s = new_line;
-                        }
-                    }
-                }
-            }
-        }
+    fn fragments(
+        &self,
+        lines: &[CodeLine],
+        originals: &[&str],
+        capture: &[Range<usize>],
+        protocol: &Protocol,
+    ) -> Vec<String> {
+        build_python_fragments(lines, originals, capture, protocol)
+    }
+
+    fn program(&self, fragments: &[String]) -> String {
+        fragments.join("\n")
+    }
+
+    fn escapes_payloads(&self) -> bool {
+        true
+    }
+
+    fn run(&self, program: &str) -> Option<Execution> {
+        let script = format!("{PRELUDE}\n{program}");
+        Some(runner::run_reporting(
+            Command::new("python3").arg("-"),
+            Some(&script),
+        ))
+    }
+
+    fn warm(&self) -> Option<&Warm> {
+        self.warm.as_ref()
+    }
+
+    fn repl_command(&self, protocol: &Protocol) -> Option<Command> {
+        let mut command = Command::new("python3");
+        command
+            .arg("-u")
+            .arg("-c")
+            .arg(format!("{PRELUDE}\n{DRIVER}"))
+            .arg(protocol.tag(Channel::Done, 0))
+            .arg(protocol.tag(Channel::Failure, 0));
+        Some(command)
     }
 }
 
@@ -97,36 +181,68 @@ fn extract_assigned_var(code: &str) -> Option<&str> {
         .filter(|s| !s.is_empty())
 }
 
-fn build_python_script(input: &[CodeLine]) -> String {
-    input
-        .iter()
-        .enumerate()
-        .map(|(i, line)| match line {
-            CodeLine::Code { code } => code.to_string(),
-            CodeLine::Eval { code, .. } => format!("print('##RESULT:{}', {})", i, code),
-            CodeLine::EvalAssignment { var, code, .. } => {
-                format!("{}\nprint('##RESULT:{}', {})", code, i, var)
-            }
-        })
-        .collect::<Vec<_>>()
-        .join("\n")
+/// A single-quoted Python literal holding `text`.
+fn python_string(text: &str) -> String {
+    let mut literal = String::from("'");
+    for ch in text.chars() {
+        match ch {
+            '\\' => literal.push_str("\\\\"),
+            '\'' => literal.push_str("\\'"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            _ => literal.push(ch),
+        }
+    }
+    literal.push('\'');
+    literal
 }
 
-// Run the generated Python code and return stdout
-fn run_python(script: &str) -> String {
-    let output = Command::new("python3")
-        .arg("-")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .and_then(|mut child| {
-            if let Some(stdin) = child.stdin.as_mut() {
-                stdin.write_all(script.as_bytes()).ok();
+/// Builds the script for each line, keeping its indentation from
+/// `originals` so loops and function bodies survive. Marked lines report
+/// their value, or the exception they raised, and the script carries on.
+fn build_python_fragments(
+    input: &[CodeLine],
+    originals: &[&str],
+    capture: &[Range<usize>],
+    protocol: &Protocol,
+) -> Vec<String> {
+    let mut fragments = Vec::new();
+    for (i, line) in input.iter().enumerate() {
+        let mut script = Vec::new();
+        if let Some(group) = capture.iter().position(|range| range.start == i) {
+            let tag = protocol.tag(Channel::Begin, group);
+            script.push(format!("_equals_emit('{tag}', '')"));
+        }
+        let original = originals[i];
+        let indent = &original[..original.len() - original.trim_start().len()];
+        let (statement, value) = match line {
+            CodeLine::Code { code } => (Some(*code), None),
+            CodeLine::Eval { code, .. } => (None, Some(*code)),
+            CodeLine::EvalAssignment { var, code, .. } => (Some(*code), Some(*var)),
+        };
+        match value {
+            None => script.push(format!("{indent}{}", statement.unwrap_or_default())),
+            Some(value) => {
+                let result = protocol.tag(Channel::Result, i);
+                let error = protocol.tag(Channel::Error, i);
+                script.push(format!("{indent}try:"));
+                if let Some(statement) = statement {
+                    script.push(format!("{indent}    {statement}"));
+                }
+                script.push(format!("{indent}    _equals_emit('{result}', {value})"));
+                script.push(format!("{indent}except Exception as _equals_error:"));
+                script.push(format!(
+                    "{indent}    _equals_emit('{error}', type(_equals_error).__name__ + ': ' + str(_equals_error))"
+                ));
             }
-            child.wait_with_output()
-        })
-        .expect("failed to run python");
-    String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+        if capture.iter().any(|range| range.end == i + 1) {
+            let tag = protocol.tag(Channel::End, 0);
+            script.push(format!("_equals_emit('{tag}', '')"));
+        }
+        fragments.push(script.join("\n"));
+    }
+    fragments
 }
 
 #[cfg(test)]
@@ -136,8 +252,8 @@ mod tests {
 
     // Simple wrapper to call PythonLang::evaluate on &mut [String]
     fn eval_blocks(blocks: &mut [String]) {
-        let lang = PythonLang;
-        let snapshots: Vec<String> = blocks.iter().cloned().collect();
+        let lang = PythonLang::default();
+        let snapshots: Vec<String> = blocks.to_vec();
         let code_blocks: Vec<CodeBlock> = snapshots
             .iter()
             .enumerate()
@@ -281,6 +397,23 @@ sum(values) #= 6
         );
     }
 
+    #[test]
+    fn custom_marker_and_precision() {
+        let lang = PythonLang::with_options(LangOptions {
+            marker: Some("=>".into()),
+            precision: Some(3),
+            ..LangOptions::default()
+        });
+        let blocks = [CodeBlock {
+            id: BlockId::new(0),
+            content: "1 / 3 =>",
+        }];
+
+        let updates = lang.evaluate(&blocks);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].content, "1 / 3 => 0.333");
+    }
+
     #[test]
     fn assignment_in_equals_line() {
         let mut code_blocks = lines(
@@ -307,4 +440,160 @@ c = a + b #= 3
             code_blocks
         );
     }
+
+    #[test]
+    fn captures_printed_output_per_range() {
+        let lang = PythonLang::default();
+        let contents = [
+            "print('setup')",
+            "for i in range(2):",
+            "    print(i)",
+            "x = 2 #=",
+        ];
+        let blocks: Vec<CodeBlock> = contents
+            .iter()
+            .enumerate()
+            .map(|(idx, content)| CodeBlock {
+                id: BlockId::new(idx),
+                content,
+            })
+            .collect();
+
+        let evaluation = lang.evaluate_capturing(&blocks, std::slice::from_ref(&(1..4)));
+        assert_eq!(evaluation.outputs, vec!["0\n1\n".to_string()]);
+        assert_eq!(evaluation.updates.len(), 1);
+        assert_eq!(evaluation.updates[0].content, "x = 2 #= 2");
+    }
+
+    #[test]
+    fn printed_records_cannot_spoof_results() {
+        let lang = PythonLang::default();
+        let contents = [
+            "print('##RESULT:1 hacked')",
+            "'a\\nb' #=",
+            "1 / 0 #=",
+            "2 + 2 #=",
+        ];
+        let blocks: Vec<CodeBlock> = contents
+            .iter()
+            .enumerate()
+            .map(|(idx, content)| CodeBlock {
+                id: BlockId::new(idx),
+                content,
+            })
+            .collect();
+
+        let evaluation = lang.evaluate_capturing(&blocks, &[]);
+        let updates: Vec<_> = evaluation
+            .updates
+            .iter()
+            .map(|update| (update.id.index(), update.content.as_str()))
+            .collect();
+        assert_eq!(updates, vec![(1, "'a\\nb' #= a b"), (3, "2 + 2 #= 4")]);
+        assert_eq!(
+            evaluation.errors,
+            vec!["1 / 0 #=: ZeroDivisionError: division by zero".to_string()]
+        );
+    }
+
+    #[test]
+    fn warm_interpreter_runs_only_new_lines() {
+        let lang = PythonLang::with_options(LangOptions {
+            persistent: true,
+            ..LangOptions::default()
+        });
+        let evaluate = |contents: &[&str]| {
+            let blocks: Vec<CodeBlock> = contents
+                .iter()
+                .enumerate()
+                .map(|(idx, content)| CodeBlock {
+                    id: BlockId::new(idx),
+                    content,
+                })
+                .collect();
+            lang.evaluate(&blocks)
+                .into_iter()
+                .map(|update| update.content)
+                .collect::<Vec<_>>()
+        };
+
+        let first = evaluate(&["import os", "os.getpid() #="]);
+        let pid = first[0].trim_start_matches("os.getpid() #= ").to_string();
+
+        // Appending keeps the process; the earlier result is replayed.
+        let appended = evaluate(&["import os", "os.getpid() #=", "os.getpid() #="]);
+        assert_eq!(appended, vec![first[0].clone(), first[0].clone()]);
+
+        // Changing an earlier line restarts it.
+        let changed = evaluate(&["import os, sys", "os.getpid() #="]);
+        assert_ne!(changed[0].trim_start_matches("os.getpid() #= "), pid);
+    }
+
+    #[test]
+    fn warm_failures_are_reported_once() {
+        let lang = PythonLang::with_options(LangOptions {
+            persistent: true,
+            ..LangOptions::default()
+        });
+        let evaluate = |contents: &[&str]| {
+            let blocks: Vec<CodeBlock> = contents
+                .iter()
+                .enumerate()
+                .map(|(idx, content)| CodeBlock {
+                    id: BlockId::new(idx),
+                    content,
+                })
+                .collect();
+            lang.evaluate_capturing(&blocks, &[]).errors
+        };
+
+        let first = evaluate(&["x = 1", "raise ValueError('boom')", "x #="]);
+        assert!(first.iter().any(|error| error.contains("boom")));
+
+        let appended = evaluate(&["x = 1", "raise ValueError('boom')", "x #=", "x + 1 #="]);
+        assert!(appended.is_empty(), "{appended:?}");
+    }
+
+    #[test]
+    fn warm_stderr_and_line_errors_are_reported_once() {
+        let lang = PythonLang::with_options(LangOptions {
+            persistent: true,
+            ..LangOptions::default()
+        });
+        let evaluate = |contents: &[&str]| {
+            let blocks: Vec<CodeBlock> = contents
+                .iter()
+                .enumerate()
+                .map(|(idx, content)| CodeBlock {
+                    id: BlockId::new(idx),
+                    content,
+                })
+                .collect();
+            lang.evaluate_capturing(&blocks, &[]).errors
+        };
+
+        let lines = ["import sys", "sys.stderr.write('oops\\n')", "y #="];
+        let first = evaluate(&lines);
+        assert!(
+            first.iter().any(|error| error.contains("oops")),
+            "{first:?}"
+        );
+        assert!(
+            first.iter().any(|error| error.starts_with("y #=")),
+            "{first:?}"
+        );
+
+        let appended = evaluate(&[lines[0], lines[1], lines[2], "1 + 1 #="]);
+        assert!(appended.is_empty(), "{appended:?}");
+    }
+
+    #[test]
+    fn asserts_compare_with_python_literals() {
+        let lang = PythonLang::default();
+        assert_eq!(
+            lang.tangle_line("s #= it's C:\\tmp", Markers::Assert),
+            "assert str(s) == 'it\\'s C:\\\\tmp'"
+        );
+        assert_eq!(python_string("a\nb \"é\""), "'a\\nb \"é\"'");
+    }
 }
@@ -0,0 +1,269 @@
+use std::env;
+use std::path::PathBuf;
+
+use crate::{
+    config::Config,
+    lang::{
+        Lexer, fend, numbat,
+        plugin::{PLUGIN_PREFIX, find_program},
+        python,
+    },
+};
+
+/// What a built-in language declares about itself.
+#[derive(Debug)]
+pub struct LanguageInfo {
+    pub name: &'static str,
+    /// Other names accepted by `--language` and front matter.
+    pub aliases: &'static [&'static str],
+    /// File extensions (lowercase, without the dot) evaluated in it.
+    pub extensions: &'static [&'static str],
+    /// Code fence and block tags besides the name and aliases.
+    pub fences: &'static [&'static str],
+    /// The eval marker, unless a document or `--marker` picks another.
+    pub marker: &'static str,
+    /// The line comment token.
+    pub comment: &'static str,
+    /// Its string literals, so markers inside them are ignored.
+    pub lexer: &'static Lexer,
+}
+
+impl LanguageInfo {
+    /// Whether `name` is the language's name, an alias or a fence tag.
+    pub fn is_called(&self, name: &str) -> bool {
+        let known = |names: &[&str]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+        self.name.eq_ignore_ascii_case(name) || known(self.aliases) || known(self.fences)
+    }
+}
+
+pub const BUILTINS: &[&LanguageInfo] = &[&python::INFO, &numbat::INFO, &fend::INFO];
+
+/// Where a registered language comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    BuiltIn,
+    Config,
+    Plugin(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub extensions: Vec<String>,
+    pub fences: Vec<String>,
+    pub origin: Origin,
+}
+
+impl Entry {
+    fn is_called(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Every language equals can evaluate in a document, with the names,
+/// extensions and fence tags that select it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+/// The built-in languages alone, for parsers used without a config.
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new(&Config::default())
+    }
+}
+
+impl Registry {
+    /// The built-in languages and those defined in `config`, which come
+    /// first so they can take over a built-in name or extension. A config
+    /// language named like a built-in replaces it, keeping its aliases,
+    /// extensions and fence tags. Declared plugins whose executable is
+    /// missing are left out.
+    pub fn new(config: &Config) -> Self {
+        let configured = config.languages.iter().map(|spec| {
+            let replaced = BUILTINS.iter().copied().find(|info| info.name == spec.name);
+            let inherit = |own: &[String], builtin: fn(&LanguageInfo) -> &[&str]| {
+                let mut names = own.to_vec();
+                for name in replaced.map_or(&[][..], builtin) {
+                    if !names.iter().any(|n| n == name) {
+                        names.push(name.to_string());
+                    }
+                }
+                names
+            };
+            Entry {
+                name: spec.name.clone(),
+                aliases: inherit(&spec.aliases, |info| info.aliases),
+                extensions: inherit(&spec.extensions, |info| info.extensions),
+                fences: inherit(&spec.fences, |info| info.fences),
+                origin: Origin::Config,
+            }
+        });
+        let declared = config.plugins.iter().filter_map(|spec| {
+            let program = spec.path.clone().or_else(|| find_program(&spec.name))?;
+            Some(Entry {
+                name: spec.name.clone(),
+                aliases: spec.aliases.clone(),
+                extensions: spec.extensions.clone(),
+                fences: spec.fences.clone(),
+                origin: Origin::Plugin(program),
+            })
+        });
+        let builtin = BUILTINS
+            .iter()
+            .filter(|info| config.language(info.name).is_none())
+            .map(|info| Entry {
+                name: info.name.to_string(),
+                aliases: to_strings(info.aliases),
+                extensions: to_strings(info.extensions),
+                fences: to_strings(info.fences),
+                origin: Origin::BuiltIn,
+            });
+        Self {
+            entries: configured.chain(declared).chain(builtin).collect(),
+        }
+    }
+
+    /// Adds the `equals-lang-<name>` plugins found on the search path that
+    /// aren't shadowed by another language.
+    pub fn with_plugins(mut self) -> Self {
+        let dirs = [env::var_os("EQUALS_PLUGIN_PATH"), env::var_os("PATH")];
+        let mut plugins: Vec<Entry> = Vec::new();
+        for dir in dirs.iter().flatten().flat_map(env::split_paths) {
+            let Ok(files) = dir.read_dir() else {
+                continue;
+            };
+            for file in files.flatten() {
+                let file_name = file.file_name();
+                let Some(name) = file_name
+                    .to_str()
+                    .and_then(|n| n.strip_prefix(PLUGIN_PREFIX))
+                else {
+                    continue;
+                };
+                if self.resolve(name).is_some() || plugins.iter().any(|p| p.name == name) {
+                    continue;
+                }
+                plugins.push(Entry {
+                    name: name.to_string(),
+                    aliases: Vec::new(),
+                    extensions: Vec::new(),
+                    fences: Vec::new(),
+                    origin: Origin::Plugin(file.path()),
+                });
+            }
+        }
+        plugins.sort_by(|a, b| a.name.cmp(&b.name));
+        self.entries.extend(plugins);
+        self
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The canonical name for a name or alias.
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        self.find(|entry| entry.is_called(name))
+    }
+
+    /// The language owning a file extension.
+    pub fn for_extension(&self, ext: &str) -> Option<&str> {
+        self.find(|entry| entry.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+    }
+
+    /// The language a code fence tag selects.
+    pub fn for_fence(&self, tag: &str) -> Option<&str> {
+        self.find(|entry| {
+            entry.is_called(tag) || entry.fences.iter().any(|f| f.eq_ignore_ascii_case(tag))
+        })
+    }
+
+    fn find(&self, pred: impl Fn(&Entry) -> bool) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|entry| pred(entry))
+            .map(|entry| entry.name.as_str())
+    }
+}
+
+fn to_strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    #[test]
+    fn builtins_resolve_names_extensions_and_fences() {
+        let registry = Registry::new(&Config::default());
+        assert_eq!(registry.resolve("PY"), Some("python"));
+        assert_eq!(registry.resolve("nbt"), Some("numbat"));
+        assert_eq!(registry.resolve("lolcode"), None);
+        assert_eq!(registry.for_extension("FD"), Some("fend"));
+        assert_eq!(registry.for_extension("md"), None);
+        assert_eq!(registry.for_fence("python3"), Some("python"));
+        assert_eq!(registry.for_fence("Nb"), Some("numbat"));
+        assert_eq!(registry.for_fence("r"), None);
+        assert!(numbat::INFO.is_called("NBT"));
+    }
+
+    #[test]
+    fn config_languages_come_first() {
+        let config = parse_config(
+            r#"
+[languages.node]
+command = "node"
+print = 'console.log("{sentinel}", {expr})'
+aliases = ["js"]
+extensions = ["js", "mjs"]
+fences = ["javascript"]
+
+[languages.python]
+command = "python3.12"
+print = 'print("{sentinel}", {expr})'
+extensions = ["py"]
+"#,
+        )
+        .unwrap();
+        let registry = Registry::new(&config);
+
+        assert_eq!(registry.resolve("js"), Some("node"));
+        assert_eq!(registry.for_extension("mjs"), Some("node"));
+        assert_eq!(registry.for_fence("javascript"), Some("node"));
+        assert_eq!(registry.entries()[1].origin, Origin::Config);
+        // The config entry replaces the built-in, keeping its names.
+        assert_eq!(registry.resolve("python3"), Some("python"));
+        assert_eq!(registry.for_extension("pyw"), Some("python"));
+        let pythons = registry.entries().iter().filter(|e| e.name == "python");
+        assert_eq!(pythons.count(), 1);
+        assert_eq!(registry.entries()[2].name, "numbat");
+    }
+
+    #[test]
+    fn declared_plugins_register_their_names() {
+        let config = parse_config(
+            r#"
+[plugins.dsl]
+path = "tools/dsl-equals"
+extensions = ["dsl"]
+
+[plugins.equals-test-missing]
+"#,
+        )
+        .unwrap();
+        let registry = Registry::new(&config);
+
+        assert_eq!(registry.for_extension("dsl"), Some("dsl"));
+        assert_eq!(
+            registry.entries()[0].origin,
+            Origin::Plugin(PathBuf::from("tools/dsl-equals"))
+        );
+        assert_eq!(registry.resolve("equals-test-missing"), None);
+    }
+}
@@ -0,0 +1,656 @@
+//! Shared plumbing for languages that evaluate by running an interpreter on
+//! a generated program.
+//!
+//! The program reports back on stdout through records tagged with a nonce
+//! picked fresh for every run, so user code can't forge them:
+//!
+//! ```text
+//! ##<nonce>:<channel>:<index>:<payload>
+//! ```
+//!
+//! `result` and `error` records carry the value or error message of the
+//! line at `<index>`; `begin` and `end` bracket capture group `<index>`.
+//! Languages whose values may span lines (Python) escape `\` as `\\` and
+//! newlines as `\n` in payloads, so a record always fits on one line.
+//! Everything else on stdout is the user's own output.
+//!
+//! Languages with a REPL can also keep one interpreter warm between
+//! evaluations (see `Warm`). By default it reads chunks as a byte length on
+//! its own line followed by the code, and answers each with a `done`
+//! record. Without a REPL, an unchanged program replays its last output.
+//! Either way, errors are reported once, by the evaluation that ran them.
+
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    document::{CodeBlock, CodeBlockUpdate, Evaluation},
+    lang::{CodeLine, LangOptions},
+};
+
+/// The language-specific half of a script-based evaluator.
+pub trait ScriptRunner {
+    fn options(&self) -> &LangOptions;
+
+    /// Parses one line of code.
+    fn split<'a>(&'a self, line: &'a str) -> CodeLine<'a>;
+
+    /// The program text for each of `lines`, reporting through `protocol`.
+    /// `originals` are the unparsed lines.
+    fn fragments(
+        &self,
+        lines: &[CodeLine],
+        originals: &[&str],
+        capture: &[Range<usize>],
+        protocol: &Protocol,
+    ) -> Vec<String>;
+
+    /// Joins fragments into a program.
+    fn program(&self, fragments: &[String]) -> String;
+
+    /// Whether payloads are escaped. Others print values as they are.
+    fn escapes_payloads(&self) -> bool {
+        false
+    }
+
+    /// Runs a whole program in a fresh interpreter. `None` if there's
+    /// nothing to run.
+    fn run(&self, program: &str) -> Option<Execution>;
+
+    /// The warm interpreter, when the language keeps one.
+    fn warm(&self) -> Option<&Warm> {
+        None
+    }
+
+    /// Starts a long-lived interpreter speaking the chunk protocol.
+    fn repl_command(&self, _protocol: &Protocol) -> Option<Command> {
+        None
+    }
+
+    /// Frames `chunk` for the REPL so it answers with a `done` record.
+    fn repl_chunk(&self, chunk: &str, _protocol: &Protocol) -> String {
+        format!("{}\n{chunk}", chunk.len())
+    }
+}
+
+/// The record channels of the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// The value of a marked line.
+    Result,
+    /// An error raised while evaluating a marked line.
+    Error,
+    /// An error that stopped the program, not tied to a marked line.
+    Failure,
+    /// Starts capturing user output into a group.
+    Begin,
+    /// Stops capturing user output.
+    End,
+    /// A REPL finished running a chunk.
+    Done,
+}
+
+impl Channel {
+    fn name(self) -> &'static str {
+        match self {
+            Channel::Result => "result",
+            Channel::Error => "error",
+            Channel::Failure => "failure",
+            Channel::Begin => "begin",
+            Channel::End => "end",
+            Channel::Done => "done",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "result" => Channel::Result,
+            "error" => Channel::Error,
+            "failure" => Channel::Failure,
+            "begin" => Channel::Begin,
+            "end" => Channel::End,
+            "done" => Channel::Done,
+            _ => return None,
+        })
+    }
+}
+
+/// The record format of one run.
+#[derive(Debug, Clone)]
+pub struct Protocol {
+    prefix: String,
+}
+
+/// What a program wrote.
+#[derive(Debug, Clone, Default)]
+pub struct Execution {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// The records of one run, sorted by channel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Line index and value, in the order they were printed.
+    pub results: Vec<(usize, String)>,
+    /// Line index and error message.
+    pub errors: Vec<(usize, String)>,
+    /// Errors that stopped the program.
+    pub failures: Vec<String>,
+    /// User output of each capture group.
+    pub outputs: Vec<String>,
+}
+
+impl Protocol {
+    /// A protocol with a fresh random nonce.
+    pub fn new() -> Self {
+        Self::with_nonce(&nonce())
+    }
+
+    pub fn with_nonce(nonce: &str) -> Self {
+        Self {
+            prefix: format!("##{nonce}:"),
+        }
+    }
+
+    /// The start of a record, up to its payload: `##<nonce>:result:3:`.
+    pub fn tag(&self, channel: Channel, index: usize) -> String {
+        format!("{}{}:{index}:", self.prefix, channel.name())
+    }
+
+    /// Sorts `stdout` into records and user output for `groups` capture
+    /// groups, unescaping `escaped` payloads.
+    pub fn parse(&self, stdout: &str, groups: usize, escaped: bool) -> Report {
+        let decode = |payload: &str| {
+            if escaped {
+                unescape(payload)
+            } else {
+                payload.to_string()
+            }
+        };
+        let mut report = Report {
+            outputs: vec![String::new(); groups],
+            ..Report::default()
+        };
+        let mut capturing: Option<usize> = None;
+
+        for line in stdout.split_inclusive('\n') {
+            let Some(start) = line.find(&self.prefix) else {
+                if let Some(output) = capturing.and_then(|group| report.outputs.get_mut(group)) {
+                    output.push_str(line);
+                }
+                continue;
+            };
+            // Output printed without a trailing newline precedes the record.
+            if let Some(output) = capturing.and_then(|group| report.outputs.get_mut(group)) {
+                output.push_str(&line[..start]);
+            }
+
+            let record = line[start + self.prefix.len()..].trim_end_matches(['\n', '\r']);
+            let mut fields = record.splitn(3, ':');
+            let (Some(channel), Some(Ok(index)), Some(payload)) = (
+                fields.next().and_then(Channel::from_name),
+                fields.next().map(str::parse::<usize>),
+                fields.next(),
+            ) else {
+                continue;
+            };
+            match channel {
+                Channel::Result => report.results.push((index, decode(payload))),
+                Channel::Error => report.errors.push((index, decode(payload))),
+                Channel::Failure => report.failures.push(decode(payload)),
+                Channel::Begin => capturing = Some(index),
+                Channel::End => capturing = None,
+                Channel::Done => {}
+            }
+        }
+        report
+    }
+
+    /// `stdout` without its error and failure records, once they have been
+    /// reported, so a later evaluation doesn't report them again.
+    fn forget_errors(&self, stdout: &str) -> String {
+        let records = [Channel::Error, Channel::Failure]
+            .map(|channel| format!("{}{}:", self.prefix, channel.name()));
+        stdout
+            .split_inclusive('\n')
+            .map(|line| {
+                // Output printed without a trailing newline is kept.
+                let start = records.iter().filter_map(|record| line.find(record)).min();
+                start.map_or(line, |start| &line[..start])
+            })
+            .collect()
+    }
+}
+
+/// A long-lived interpreter for one session. It remembers the fragments it
+/// has run, so a later evaluation only sends the lines after them; when an
+/// earlier line changed, the interpreter restarts from scratch. Languages
+/// without a REPL rerun only when their program changed.
+#[derive(Default)]
+pub struct Warm {
+    repl: RefCell<Option<Repl>>,
+    /// The last program run without a REPL, and what it printed.
+    last: RefCell<Option<(Protocol, String, Execution)>>,
+}
+
+struct Repl {
+    protocol: Protocol,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    /// Fragments run so far.
+    history: Vec<String>,
+    /// Everything printed so far, records included.
+    log: String,
+    /// Lines the interpreter writes on stderr, read on another thread.
+    stderr: Receiver<String>,
+}
+
+/// How long to wait for more stderr once a chunk is done. The interpreter
+/// writes it before the `done` record, but the reader may be catching up.
+const STDERR_GRACE: Duration = Duration::from_millis(20);
+
+impl Warm {
+    /// Brings the interpreter up to date with `lines` and returns its
+    /// protocol and everything it printed for them. `None` if there's
+    /// nothing to run.
+    fn execute(
+        &self,
+        runner: &impl ScriptRunner,
+        lines: &[CodeLine],
+        originals: &[&str],
+        capture: &[Range<usize>],
+    ) -> io::Result<Option<(Protocol, Execution)>> {
+        let mut slot = self.repl.borrow_mut();
+        if let Some(repl) = slot.as_ref() {
+            let fragments = runner.fragments(lines, originals, capture, &repl.protocol);
+            if !fragments.starts_with(&repl.history) {
+                *slot = None;
+            }
+        }
+        let repl = match slot.as_mut() {
+            Some(repl) => repl,
+            None => match Repl::start(runner) {
+                Ok(repl) => slot.insert(repl),
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                    return Ok(self.replay(runner, lines, originals, capture));
+                }
+                Err(err) => return Err(err),
+            },
+        };
+
+        let fragments = runner.fragments(lines, originals, capture, &repl.protocol);
+        let pending = &fragments[repl.history.len()..];
+        let mut stderr = String::new();
+        if !pending.is_empty() {
+            let chunk = runner.repl_chunk(&runner.program(pending), &repl.protocol);
+            match repl.send(&chunk) {
+                Ok(written) => stderr = written,
+                Err(err) => {
+                    *slot = None;
+                    return Err(err);
+                }
+            }
+            repl.history.extend_from_slice(pending);
+        }
+
+        let execution = Execution {
+            stdout: repl.log.clone(),
+            stderr,
+        };
+        repl.log = repl.protocol.forget_errors(&repl.log);
+        Ok(Some((repl.protocol.clone(), execution)))
+    }
+
+    /// Runs the program in a fresh interpreter unless it's the one run last
+    /// time, whose output is returned again without its errors.
+    fn replay(
+        &self,
+        runner: &impl ScriptRunner,
+        lines: &[CodeLine],
+        originals: &[&str],
+        capture: &[Range<usize>],
+    ) -> Option<(Protocol, Execution)> {
+        let mut last = self.last.borrow_mut();
+        if let Some((protocol, program, execution)) = last.as_ref() {
+            let fragments = runner.fragments(lines, originals, capture, protocol);
+            if runner.program(&fragments) == *program {
+                return Some((protocol.clone(), execution.clone()));
+            }
+        }
+        let (protocol, program, execution) = run_cold(runner, lines, originals, capture)?;
+        let reported = Execution {
+            stdout: protocol.forget_errors(&execution.stdout),
+            stderr: String::new(),
+        };
+        *last = Some((protocol.clone(), program, reported));
+        Some((protocol, execution))
+    }
+}
+
+impl Repl {
+    fn start(runner: &impl ScriptRunner) -> io::Result<Self> {
+        let protocol = Protocol::new();
+        let mut command = runner
+            .repl_command(&protocol)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no REPL"))?;
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(io::Error::other("REPL pipes unavailable"));
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            protocol,
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            history: Vec::new(),
+            log: String::new(),
+            stderr: receiver,
+        })
+    }
+
+    /// Runs one framed chunk, appends its output to the log and returns
+    /// what it wrote on stderr.
+    fn send(&mut self, chunk: &str) -> io::Result<String> {
+        self.stdin.write_all(chunk.as_bytes())?;
+        self.stdin.flush()?;
+
+        let done = self.protocol.tag(Channel::Done, 0);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            if let Some(end) = line.find(&done) {
+                self.log.push_str(&line[..end]);
+                break;
+            }
+            self.log.push_str(&line);
+        }
+
+        let mut stderr = String::new();
+        while let Ok(line) = self.stderr.recv_timeout(STDERR_GRACE) {
+            stderr.push_str(&line);
+            stderr.push('\n');
+        }
+        Ok(stderr)
+    }
+}
+
+impl Drop for Repl {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Evaluates `blocks` with `runner`: marked lines get their results and
+/// `capture` ranges collect the user output printed while they ran.
+pub fn evaluate(
+    runner: &impl ScriptRunner,
+    blocks: &[CodeBlock],
+    capture: &[Range<usize>],
+) -> Evaluation {
+    let mut evaluation = Evaluation {
+        outputs: vec![String::new(); capture.len()],
+        ..Evaluation::default()
+    };
+
+    let originals: Vec<&str> = blocks.iter().map(|block| block.content).collect();
+    let lines: Vec<CodeLine> = originals.iter().map(|line| runner.split(line)).collect();
+    let has_eval = lines
+        .iter()
+        .any(|line| !matches!(line, CodeLine::Code { .. }));
+    if !has_eval && capture.is_empty() {
+        return evaluation;
+    }
+
+    let run = match runner.warm() {
+        Some(warm) => match warm.execute(runner, &lines, &originals, capture) {
+            Ok(run) => run,
+            Err(err) => {
+                evaluation.errors.push(format!("interpreter failed: {err}"));
+                return evaluation;
+            }
+        },
+        None => run_cold(runner, &lines, &originals, capture)
+            .map(|(protocol, _, execution)| (protocol, execution)),
+    };
+    let Some((protocol, execution)) = run else {
+        return evaluation;
+    };
+    let report = protocol.parse(&execution.stdout, capture.len(), runner.escapes_payloads());
+    apply_report(runner.options(), blocks, &lines, report, &execution.stderr)
+}
+
+/// Runs `lines` as one program in a fresh interpreter, returning the
+/// protocol, the program and what it printed.
+fn run_cold(
+    runner: &impl ScriptRunner,
+    lines: &[CodeLine],
+    originals: &[&str],
+    capture: &[Range<usize>],
+) -> Option<(Protocol, String, Execution)> {
+    let protocol = Protocol::new();
+    let program = runner.program(&runner.fragments(lines, originals, capture, &protocol));
+    let execution = runner.run(&program)?;
+    Some((protocol, program, execution))
+}
+
+/// Turns what an interpreter reported into block updates and errors.
+pub fn apply_report(
+    options: &LangOptions,
+    blocks: &[CodeBlock],
+    lines: &[CodeLine],
+    report: Report,
+    stderr: &str,
+) -> Evaluation {
+    // A line inside a loop reports once per iteration; the last value wins.
+    let mut working: Vec<Option<String>> = vec![None; blocks.len()];
+    for (idx, value) in &report.results {
+        let (Some(line), Some(block)) = (lines.get(*idx), blocks.get(*idx)) else {
+            continue;
+        };
+        let original = block.content;
+        let indent = &original[..original.len() - original.trim_start().len()];
+        let result = options.format_result(&single_line(value));
+        working[*idx] = Some(format!("{indent}{}", line.reconstruct(&result)));
+    }
+    let updates = blocks
+        .iter()
+        .zip(working)
+        .filter_map(|(block, content)| {
+            let content = content.filter(|content| content != block.content)?;
+            Some(CodeBlockUpdate {
+                id: block.id,
+                content,
+            })
+        })
+        .collect();
+
+    let mut errors: Vec<String> = report
+        .errors
+        .iter()
+        .map(|(idx, message)| match blocks.get(*idx) {
+            Some(block) => format!("{}: {message}", block.content.trim()),
+            None => message.clone(),
+        })
+        .chain(
+            report
+                .failures
+                .iter()
+                .map(|failure| failure.trim().to_string()),
+        )
+        .collect();
+    let stderr = stderr.trim();
+    if !stderr.is_empty() {
+        errors.push(stderr.to_string());
+    }
+    Evaluation {
+        updates,
+        outputs: report.outputs,
+        errors,
+    }
+}
+
+/// Runs `command`, feeding it `stdin` if given, and collects its output.
+/// Like `run`, but an interpreter that can't be started is reported on
+/// stderr, as its own errors would be.
+pub fn run_reporting(command: &mut Command, stdin: Option<&str>) -> Execution {
+    run(command, stdin).unwrap_or_else(|err| Execution {
+        stderr: format!("{}: {err}", command.get_program().to_string_lossy()),
+        ..Execution::default()
+    })
+}
+
+pub fn run(command: &mut Command, stdin: Option<&str>) -> io::Result<Execution> {
+    let mut child = command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    Ok(Execution {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// A value that printed over several lines, joined to fit after a marker.
+fn single_line(value: &str) -> String {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Reverses the payload escaping; unknown escapes are kept as written.
+fn unescape(payload: &str) -> String {
+    let mut out = String::with_capacity(payload.len());
+    let mut chars = payload.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// 64 random bits in hex, from the standard library's per-process hash keys
+/// mixed with the time and process id.
+fn nonce() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    hasher.write_u128(nanos);
+    hasher.write_u32(std::process::id());
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_sorted_by_channel() {
+        let protocol = Protocol::with_nonce("abc");
+        let stdout = "\
+setup
+##abc:begin:0:
+hello
+partial##abc:result:2:a\\nb \\\\ c
+##abc:end:0:
+##abc:error:3:ZeroDivisionError: division by zero
+after
+";
+        let report = protocol.parse(stdout, 1, true);
+
+        assert_eq!(report.results, vec![(2, "a\nb \\ c".to_string())]);
+        assert_eq!(
+            report.errors,
+            vec![(3, "ZeroDivisionError: division by zero".to_string())]
+        );
+        assert_eq!(report.outputs, vec!["hello\npartial".to_string()]);
+    }
+
+    #[test]
+    fn records_with_another_nonce_are_user_output() {
+        let protocol = Protocol::with_nonce("abc");
+        let stdout = "##abc:begin:0:\n##RESULT:0 hacked\n##xyz:result:0:spoofed\n";
+        let report = protocol.parse(stdout, 1, false);
+
+        assert!(report.results.is_empty());
+        assert_eq!(
+            report.outputs,
+            vec!["##RESULT:0 hacked\n##xyz:result:0:spoofed\n".to_string()]
+        );
+    }
+
+    #[test]
+    fn unescaped_payloads_are_kept_as_printed() {
+        let protocol = Protocol::with_nonce("abc");
+        let report = protocol.parse("##abc:result:0:C:\\new\\n\n", 0, false);
+        assert_eq!(report.results, vec![(0, "C:\\new\\n".to_string())]);
+    }
+
+    #[test]
+    fn nonces_differ_between_runs() {
+        assert_ne!(
+            Protocol::new().tag(Channel::Result, 0),
+            Protocol::new().tag(Channel::Result, 0)
+        );
+        assert_eq!(
+            Protocol::with_nonce("abc").tag(Channel::Begin, 2),
+            "##abc:begin:2:"
+        );
+    }
+
+    #[test]
+    fn multi_line_values_are_joined() {
+        assert_eq!(single_line("[[1 2]\n [3 4]]"), "[[1 2] [3 4]]");
+    }
+}
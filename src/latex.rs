@@ -0,0 +1,257 @@
+use crate::{
+    document::{Block, Document, Line, Session},
+    lang::registry::Registry,
+    parser::Parser,
+};
+
+/// Parses LaTeX documents: the bodies of `lstlisting`, `minted` and
+/// `verbatim` environments, plus the inline `\equals{2*pi*r #=}` macro whose
+/// argument is evaluated in place.
+///
+/// `minted{lang}` and `lstlisting[language=lang]` pick the language; other
+/// environments use the document language. Environments in languages equals
+/// doesn't know are left untouched.
+pub struct LatexParser {
+    registry: Registry,
+}
+
+const INLINE_MACRO: &str = "\\equals{";
+
+impl LatexParser {
+    pub fn new() -> Self {
+        Self {
+            registry: Registry::default(),
+        }
+    }
+
+    /// Resolves `minted` and `lstlisting` languages through `registry`.
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    /// Recognises `\begin{env}` for a code environment. Returns the
+    /// environment name and the block's session (`None` to keep it as text).
+    fn begin_environment<'a>(&self, line: &'a str) -> Option<(&'a str, Option<Session>)> {
+        let rest = line.trim_start().strip_prefix("\\begin{")?;
+        let (env, rest) = rest.split_once('}')?;
+
+        let language = match env {
+            "verbatim" | "Verbatim" => None,
+            "lstlisting" => Self::optional_arg(rest)
+                .and_then(|opts| Self::key_value(opts, "language"))
+                .map(|l| l.trim_start_matches('{').trim_end_matches('}')),
+            "minted" => {
+                let rest = rest.trim_start();
+                let rest = match Self::optional_arg(rest) {
+                    Some(opts) => &rest[opts.len() + 2..],
+                    None => rest,
+                };
+                let lang = rest.trim_start().strip_prefix('{')?.split_once('}')?.0;
+                Some(lang)
+            }
+            _ => return None,
+        };
+
+        let session = match language {
+            Some(name) => self
+                .registry
+                .for_fence(name.trim())
+                .map(|l| Session::default().with_language(l)),
+            None => Some(Session::default()),
+        };
+        Some((env, session))
+    }
+
+    /// Returns the contents of a leading `[...]` argument.
+    fn optional_arg(text: &str) -> Option<&str> {
+        let inner = text.trim_start().strip_prefix('[')?;
+        Some(inner.split_once(']')?.0)
+    }
+
+    fn key_value<'a>(options: &'a str, key: &str) -> Option<&'a str> {
+        options.split(',').find_map(|pair| {
+            let (k, v) = pair.split_once('=')?;
+            (k.trim() == key).then(|| v.trim())
+        })
+    }
+
+    /// Splits out `\equals{...}` arguments that appear before a comment. A
+    /// `%` inside an argument is code (`\equals{10 % 3 #=}`), not a comment.
+    fn parse_inline_line(line: &str) -> Line {
+        let mut blocks = Vec::new();
+        let mut text_start = 0;
+        let mut search_from = 0;
+
+        while let Some(found) = line[search_from..].find(INLINE_MACRO) {
+            if Self::comment_start(&line[search_from..]).is_some_and(|comment| comment < found) {
+                break;
+            }
+            let code_start = search_from + found + INLINE_MACRO.len();
+            let Some(code_end) = Self::closing_brace(line, code_start) else {
+                break;
+            };
+            search_from = code_end + 1;
+
+            blocks.push(Block::Text(
+                (text_start, code_start),
+                line[text_start..code_start].to_string(),
+            ));
+            blocks.push(Block::Code(
+                (code_start, code_end),
+                line[code_start..code_end].to_string(),
+                Session::default(),
+            ));
+            text_start = code_end;
+        }
+
+        if text_start < line.len() || blocks.is_empty() {
+            blocks.push(Block::Text(
+                (text_start, line.len()),
+                line[text_start..].to_string(),
+            ));
+        }
+        Line { blocks }
+    }
+
+    /// Finds the `}` matching an opening brace just before `start`.
+    fn closing_brace(line: &str, start: usize) -> Option<usize> {
+        let mut depth = 1;
+        let mut escaped = false;
+        for (idx, ch) in line[start..].char_indices() {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(start + idx);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Position of the first unescaped `%`.
+    fn comment_start(line: &str) -> Option<usize> {
+        let mut escaped = false;
+        for (idx, ch) in line.char_indices() {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '%' => return Some(idx),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+impl Parser for LatexParser {
+    fn parse(&self, input: &str) -> Document {
+        let mut lines = Vec::new();
+        // The closing `\end{env}` and the session of the open environment.
+        let mut environment: Option<(String, Option<Session>)> = None;
+
+        for line in input.lines() {
+            if let Some((end, session)) = &environment {
+                if line.trim_start().starts_with(end.as_str()) {
+                    environment = None;
+                    lines.push(Line::text(line));
+                } else if let Some(session) = session {
+                    lines.push(Line {
+                        blocks: vec![Block::Code(
+                            (0, line.len()),
+                            line.to_string(),
+                            session.clone(),
+                        )],
+                    });
+                } else {
+                    lines.push(Line::text(line));
+                }
+                continue;
+            }
+
+            if let Some((env, session)) = self.begin_environment(line) {
+                environment = Some((format!("\\end{{{env}}}"), session));
+                lines.push(Line::text(line));
+            } else {
+                lines.push(Self::parse_inline_line(line));
+            }
+        }
+
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_environments_pick_their_language() {
+        let src = "\
+\\begin{minted}[linenos]{numbat}
+let r = 2 m
+\\end{minted}
+\\begin{lstlisting}[language=Python, caption=Area]
+3.14 * 2 ** 2 #=
+\\end{lstlisting}
+\\begin{verbatim}
+1 + 1 #=
+\\end{verbatim}
+\\begin{lstlisting}[language=C]
+int x = 1;
+\\end{lstlisting}";
+        let doc = LatexParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("let r = 2 m", Some("numbat")),
+                ("3.14 * 2 ** 2 #=", Some("python")),
+                ("1 + 1 #=", None),
+            ]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn inline_macro_argument_is_code() {
+        let src =
+            "The area is \\equals{2*pi*r #=} and \\equals{f(x)[{1}] #= 3} units. % \\equals{0 #=}";
+        let doc = LatexParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("2*pi*r #=", None), ("f(x)[{1}] #= 3", None)]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn escaped_percent_is_not_a_comment() {
+        let src = "Rate 5\\% is \\equals{0.05 * 200 #=}";
+        let doc = LatexParser::new().parse(src);
+        assert_eq!(doc.code_blocks(), vec![("0.05 * 200 #=", None)]);
+    }
+
+    #[test]
+    fn percent_inside_macro_argument_is_code() {
+        let src = "\\equals{10 % 3 #=} and \\equals{7 % 4 #=} % \\equals{0 #=}";
+        let doc = LatexParser::new().parse(src);
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("10 % 3 #=", None), ("7 % 4 #=", None)]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+}
@@ -1,16 +1,49 @@
-use clap::Parser as ClapParser;
+use clap::{Args as ClapArgs, Parser as ClapParser, Subcommand};
+use std::collections::HashMap;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, BufRead, Read, Write};
 use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
+mod asciidoc;
+mod comment;
+mod config;
+mod dialect;
+mod doccomment;
 mod document;
+mod frontmatter;
+mod json;
 mod lang;
+mod latex;
 mod markdown;
+mod modeline;
+mod notebook;
+mod org;
 mod parser;
+mod rst;
+mod table;
+mod tangle;
+mod typst;
 
-use crate::lang::{Language, get_language_spec};
+use crate::asciidoc::AsciiDocParser;
+use crate::comment::{CommentParser, CommentSyntax};
+use crate::config::{Config, load_config};
+use crate::dialect::{convert, dialect_marker, find_dialect, marks_code};
+use crate::doccomment::{DocCommentParser, DocStyle};
+use crate::document::Evaluation;
+use crate::frontmatter::parse_front_matter;
+use crate::lang::registry::{Origin, Registry};
+use crate::lang::{LangOptions, Language, Lexer, Markers, get_language_spec};
+use crate::latex::LatexParser;
 use crate::markdown::MarkdownParser;
+use crate::modeline::{find_modeline, shebang_interpreter};
+use crate::notebook::Notebook;
+use crate::org::OrgParser;
 use crate::parser::{Parser, PlainParser};
+use crate::rst::RstParser;
+use crate::tangle::tangle;
+use crate::typst::TypstParser;
 
 /// equals — evaluate code inside text or markdown files
 #[derive(ClapParser, Debug)]
@@ -24,86 +57,647 @@ struct Args {
     #[arg(short, long)]
     output: Option<String>,
 
-    /// Force language (optional, e.g. "python", "numbat")
+    /// Force language (optional, e.g. "python", "numbat" or an alias like "py")
     #[arg(short, long)]
     language: Option<String>,
 
     /// Parse as Markdown (if not set, uses plain text parser)
     #[arg(short = 'm', long)]
     markdown: bool,
+
+    /// Eval marker for every language, or for one as LANG=MARKER (e.g.
+    /// "//=", "numbat=%="); repeatable, and wins over front matter. A
+    /// dialect name ("xmpfilter", "quokka") stands for its marker
+    #[arg(long, value_name = "[LANG=]MARKER")]
+    marker: Vec<String>,
+
+    /// Evaluate fenced examples in Rust doc comments or Python docstrings
+    #[arg(short = 'd', long)]
+    doc: bool,
+
+    /// Re-evaluate the input file whenever it changes, keeping interpreters
+    /// warm (writes back to the input unless --output is given)
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Evaluate documents sent on stdin, each as its byte length on a line
+    /// followed by the text, answering each the same way on stdout; keeps
+    /// interpreters warm between documents (for editor integrations)
+    #[arg(long, conflicts_with_all = ["watch", "input", "output"])]
+    serve: bool,
+
+    /// Path the stdin buffer is saved as, used to detect its format and
+    /// language (e.g. when an editor pipes a buffer)
+    #[arg(long, value_name = "PATH", conflicts_with = "input")]
+    stdin_filename: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Extract the code blocks of a Markdown document into a runnable script
+    Tangle(TangleArgs),
+    /// List the languages equals can evaluate, with their aliases,
+    /// extensions and fence tags
+    Languages,
+    /// Rewrite the markers of one dialect as another's (e.g. xmpfilter's
+    /// `# =>` as `#=`), keeping the results
+    Convert(ConvertArgs),
+}
+
+#[derive(ClapArgs, Debug)]
+struct ConvertArgs {
+    /// File to convert (if not provided, reads from stdin)
+    input: Option<String>,
+
+    /// Output file (if not provided, prints to stdout)
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Dialect or marker the input uses ("xmpfilter", "quokka", "equals",
+    /// or a marker such as "# =>")
+    #[arg(long)]
+    from: String,
+
+    /// Dialect or marker to write
+    #[arg(long)]
+    to: String,
+
+    /// Comment token that may follow a result (defaults to the
+    /// `--language`'s, the `--from` dialect's, or "#")
+    #[arg(long)]
+    comment: Option<String>,
+
+    /// Language of the code, whose string literals and comment token are
+    /// used (defaults to the `--from` dialect's)
+    #[arg(short, long)]
+    language: Option<String>,
+}
+
+#[derive(ClapArgs, Debug)]
+struct TangleArgs {
+    /// Markdown document (if not provided, reads from stdin)
+    input: Option<String>,
+
+    /// Output script (if not provided, prints to stdout)
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Language whose blocks are extracted (defaults like evaluation does)
+    #[arg(short, long)]
+    language: Option<String>,
+
+    /// Eval marker, as for evaluation
+    #[arg(long, value_name = "[LANG=]MARKER")]
+    marker: Vec<String>,
+
+    /// What to do with `#=` markers and their results
+    #[arg(long, value_enum, default_value_t = MarkersArg::Strip)]
+    markers: MarkersArg,
+
+    /// Only extract blocks of this named session (e.g. "block-2")
+    #[arg(long)]
+    session: Option<String>,
+}
+
+/// `--markers` values of `equals tangle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum MarkersArg {
+    /// Drop the marker and result, keeping the code
+    Strip,
+    /// Print the value the marker would have shown
+    Print,
+    /// Check the value against the recorded result (printing it if none)
+    Assert,
+}
+
+impl From<MarkersArg> for Markers {
+    fn from(arg: MarkersArg) -> Self {
+        match arg {
+            MarkersArg::Strip => Markers::Strip,
+            MarkersArg::Print => Markers::Print,
+            MarkersArg::Assert => Markers::Assert,
+        }
+    }
 }
 
 fn main() -> io::Result<()> {
     let mut args = Args::parse();
 
-    if args.input.as_deref().map(is_markdown_path).unwrap_or(false) {
+    match args.command.take() {
+        Some(Command::Tangle(tangle_args)) => return run_tangle(tangle_args),
+        Some(Command::Languages) => return list_languages(),
+        Some(Command::Convert(convert_args)) => return run_convert(convert_args),
+        None => {}
+    }
+
+    if source_path(&args).is_some_and(is_markdown_path) {
         args.markdown = true;
     }
 
+    if args.watch {
+        let Some(path) = args.input.clone() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--watch needs an --input file",
+            ));
+        };
+        return watch(&args, &path);
+    }
+    if args.serve {
+        return serve(&args);
+    }
+
     // --- 1. Read input
-    let input_text = if let Some(path) = args.input.as_deref() {
-        fs::read_to_string(path)?
+    let input_text = read_input(args.input.as_deref())?;
+    let output_text = evaluate_text(&args, &input_text, &mut Backends::default())?;
+
+    // --- 6. Write output
+    write_output(args.output.as_deref(), &output_text)
+}
+
+/// Language instances of a run, one per language and session. Watch and
+/// serve modes keep them between evaluations so warm interpreters survive.
+#[derive(Default)]
+struct Backends {
+    options: LangOptions,
+    config: Config,
+    languages: HashMap<(String, Option<String>), Box<dyn Language>>,
+}
+
+impl Backends {
+    fn get(
+        &mut self,
+        name: &str,
+        session: Option<&str>,
+        options: &LangOptions,
+        config: &Config,
+    ) -> Option<&dyn Language> {
+        // New front matter or config settings need fresh instances.
+        if self.options != *options || self.config != *config {
+            self.options = options.clone();
+            self.config = config.clone();
+            self.languages.clear();
+        }
+        let key = (name.to_string(), session.map(str::to_string));
+        if !self.languages.contains_key(&key) {
+            let lang = get_language_spec(name, options, config)?;
+            self.languages.insert(key.clone(), lang);
+        }
+        self.languages.get(&key).map(|lang| &**lang)
+    }
+}
+
+/// Evaluates the input file each time its contents change. When writing
+/// back in place, our own write isn't mistaken for an edit.
+fn watch(args: &Args, path: &str) -> io::Result<()> {
+    let mut backends = Backends::default();
+    let mut seen: Option<String> = None;
+    loop {
+        let input_text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            // Editors that save atomically briefly remove the file.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                thread::sleep(Duration::from_millis(250));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if seen.as_deref() != Some(input_text.as_str()) {
+            let output_text = match evaluate_text(args, &input_text, &mut backends) {
+                Ok(text) => text,
+                // A half-written notebook is retried once it changes again.
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    eprintln!("{path}: {err}");
+                    seen = Some(input_text);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            match args.output.as_deref() {
+                Some(output) => {
+                    fs::write(output, &output_text)?;
+                    seen = Some(input_text);
+                }
+                None => {
+                    if output_text != input_text {
+                        fs::write(path, &output_text)?;
+                    }
+                    seen = Some(output_text);
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Evaluates each document framed on stdin and writes the result back framed
+/// the same way. A document that can't be evaluated is answered unchanged.
+fn serve(args: &Args) -> io::Result<()> {
+    let mut backends = Backends::default();
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if stdin.read_line(&mut header)? == 0 {
+            return Ok(());
+        }
+        let len: usize = header.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected a document length, got {:?}", header.trim()),
+            )
+        })?;
+        let mut body = vec![0; len];
+        stdin.read_exact(&mut body)?;
+        let input_text = String::from_utf8(body)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let output_text = match evaluate_text(args, &input_text, &mut backends) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("{err}");
+                input_text
+            }
+            Err(err) => return Err(err),
+        };
+        write!(stdout, "{}\n{output_text}", output_text.len())?;
+        stdout.flush()?;
+    }
+}
+
+/// Parses and evaluates one version of the input, returning the new text.
+fn evaluate_text(args: &Args, input_text: &str, backends: &mut Backends) -> io::Result<String> {
+    let path = source_path(args);
+    let front_matter = if args.markdown {
+        parse_front_matter(input_text)
     } else {
-        let mut buf = String::new();
-        io::stdin().read_to_string(&mut buf)?;
-        buf
+        None
     };
+    let modeline = find_modeline(input_text);
 
+    let notebook = if path.is_some_and(is_notebook_path) {
+        let notebook = Notebook::parse(input_text)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Some(notebook)
+    } else {
+        None
+    };
+
+    let config = config_for(path)?;
+    let registry = Registry::new(&config).with_plugins();
     let markdown_guess = if args.markdown {
-        detect_markdown_language(&input_text).map(|s| s.to_string())
+        detect_markdown_language(input_text, &registry).map(|s| s.to_string())
     } else {
         None
     };
 
+    let comment_syntax = path.and_then(|path| comment_syntax_for_path(path, &config, &registry));
+
     let language_name = args
         .language
         .clone()
+        .or_else(|| front_matter.as_ref().and_then(|fm| fm.language.clone()))
+        .or_else(|| modeline.as_ref().and_then(|ml| ml.language.clone()))
+        .map(|name| canonical_name(&registry, name))
+        .or_else(|| {
+            notebook
+                .as_ref()
+                .and_then(|nb| nb.language(&registry))
+                .map(str::to_string)
+        })
         .or_else(|| {
-            args.input
-                .as_deref()
-                .and_then(|path| guess_language_from_path(Path::new(path)).map(|s| s.to_string()))
+            path.and_then(|path| guess_language_from_path(Path::new(path), &registry))
+                .or_else(|| guess_language_from_shebang(input_text, &registry))
+                .map(str::to_string)
         })
         .or(markdown_guess)
-        .unwrap_or_else(|| "python".to_string());
+        // Comments hold prose-like calculations, which suit Fend best.
+        .unwrap_or_else(|| {
+            if comment_syntax.is_some() {
+                "fend"
+            } else {
+                "python"
+            }
+            .to_string()
+        });
 
-    let lang: Box<dyn Language> = get_language_spec(&language_name)
-        .unwrap_or_else(|| panic!("Unknown language: {language_name}"));
+    let (marker, language_markers) = marker_args(&args.marker, &registry);
+    let options = LangOptions {
+        marker: marker
+            .or_else(|| front_matter.as_ref().and_then(|fm| fm.marker.clone()))
+            .or_else(|| modeline.as_ref().and_then(|ml| ml.marker.clone()))
+            .map(|marker| dialect_marker(&marker).to_string()),
+        language_markers,
+        precision: front_matter
+            .as_ref()
+            .and_then(|fm| fm.precision)
+            .or_else(|| modeline.as_ref().and_then(|ml| ml.precision)),
+        persistent: args.watch || args.serve,
+    };
+
+    let document_lang = get_language_spec(&language_name, &options, &config)
+        .ok_or_else(|| unknown_language(&language_name))?;
 
     // --- 2. Parse document
-    let parser: Box<dyn Parser> = if args.markdown {
-        Box::new(MarkdownParser::new())
-    } else {
-        Box::new(PlainParser {})
-    };
-    let mut doc = parser.parse(&input_text);
-    doc.evaluate_with(|blocks| lang.evaluate(blocks));
-    let output_text = doc.reconstruct();
+    let doc_style = path
+        .filter(|_| args.doc)
+        .and_then(|path| Path::new(path).extension()?.to_str())
+        .and_then(|ext| DocStyle::for_extension(&ext.to_ascii_lowercase()));
 
-    // --- 6. Write output
-    if let Some(path) = args.output {
-        fs::write(path, output_text)?;
+    let parser: Box<dyn Parser> = if let Some(style) = doc_style {
+        Box::new(DocCommentParser::new(style).with_registry(&registry))
+    } else if args.markdown {
+        Box::new(
+            MarkdownParser::new()
+                .with_front_matter(front_matter.as_ref())
+                .with_registry(&registry),
+        )
+    } else if path.is_some_and(is_org_path) {
+        Box::new(OrgParser::new().with_registry(&registry))
+    } else if path.is_some_and(is_asciidoc_path) {
+        Box::new(AsciiDocParser::new().with_registry(&registry))
+    } else if path.is_some_and(is_rst_path) {
+        Box::new(RstParser::new().with_registry(&registry))
+    } else if path.is_some_and(is_latex_path) {
+        Box::new(LatexParser::new().with_registry(&registry))
+    } else if path.is_some_and(is_typst_path) {
+        Box::new(TypstParser::new().with_registry(&registry))
+    } else if let Some(syntax) = comment_syntax.filter(|_| !marks_code(document_lang.eval_marker()))
+    {
+        Box::new(CommentParser::new(syntax, document_lang.eval_marker()))
     } else {
-        print!("{output_text}");
+        Box::new(PlainParser::new().with_registry(&registry))
+    };
+    let mut doc = match &notebook {
+        Some(notebook) => notebook
+            .document(&registry)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+        None => parser.parse(input_text),
+    };
+    doc.set_default_language(&language_name);
+    for (idx, message) in &doc.errors {
+        eprintln!("line {}: {message}", idx + 1);
     }
 
+    // Spans may pick their own language, so others are created on demand.
+    doc.evaluate_capturing_with(|session, blocks, capture| {
+        let name = session.language.as_deref().unwrap_or(&language_name);
+        let Some(lang) = backends.get(name, session.name.as_deref(), &options, &config) else {
+            eprintln!("{}", unknown_language(name));
+            return Evaluation::default();
+        };
+        let evaluation = lang.evaluate_capturing(blocks, capture);
+        for error in &evaluation.errors {
+            eprintln!("{name}: {error}");
+        }
+        evaluation
+    });
+    Ok(match &notebook {
+        Some(notebook) => notebook.write_back(&doc),
+        None => doc.reconstruct(),
+    })
+}
+
+fn unknown_language(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unknown language `{name}`"),
+    )
+}
+
+/// `equals tangle`: writes the blocks of one language as a standalone script.
+fn run_tangle(args: TangleArgs) -> io::Result<()> {
+    let input_text = read_input(args.input.as_deref())?;
+    let front_matter = parse_front_matter(&input_text);
+    let config = config_for(args.input.as_deref())?;
+    let registry = Registry::new(&config).with_plugins();
+
+    let language_name = args
+        .language
+        .clone()
+        .or_else(|| front_matter.as_ref().and_then(|fm| fm.language.clone()))
+        .map(|name| canonical_name(&registry, name))
+        .or_else(|| detect_markdown_language(&input_text, &registry).map(|s| s.to_string()))
+        .unwrap_or_else(|| "python".to_string());
+    let (marker, language_markers) = marker_args(&args.marker, &registry);
+    let options = LangOptions {
+        marker: marker
+            .or_else(|| front_matter.as_ref().and_then(|fm| fm.marker.clone()))
+            .map(|marker| dialect_marker(&marker).to_string()),
+        language_markers,
+        precision: front_matter.as_ref().and_then(|fm| fm.precision),
+        persistent: false,
+    };
+    let lang = get_language_spec(&language_name, &options, &config)
+        .ok_or_else(|| unknown_language(&language_name))?;
+
+    // Inline spans are prose, so only fenced blocks are tangled.
+    let mut doc = MarkdownParser::fenced_only()
+        .with_front_matter(front_matter.as_ref())
+        .with_registry(&registry)
+        .parse(&input_text);
+    doc.set_default_language(&language_name);
+    // A result rounded to `precision` won't equal the value, so print it.
+    let markers = match (Markers::from(args.markers), options.precision) {
+        (Markers::Assert, Some(_)) => Markers::Print,
+        (markers, _) => markers,
+    };
+    let script = tangle(&doc, lang.as_ref(), args.session.as_deref(), markers);
+
+    write_output(args.output.as_deref(), &script)
+}
+
+/// `equals convert`: rewrites markers from one dialect to another without
+/// evaluating anything.
+fn run_convert(args: ConvertArgs) -> io::Result<()> {
+    let input_text = read_input(args.input.as_deref())?;
+    let language = match args.language {
+        Some(name) => {
+            let config = config_for(args.input.as_deref())?;
+            let name = canonical_name(&Registry::new(&config).with_plugins(), name);
+            let lang = get_language_spec(&name, &LangOptions::default(), &config)
+                .ok_or_else(|| unknown_language(&name))?;
+            Some(lang)
+        }
+        None => None,
+    };
+    let dialect = find_dialect(&args.from);
+    let comment = args
+        .comment
+        .as_deref()
+        .or_else(|| language.as_ref().map(|lang| lang.comment()))
+        .or_else(|| dialect.map(|dialect| dialect.comment))
+        .unwrap_or("#");
+    let default_lexer = Lexer::DEFAULT;
+    let lexer = language
+        .as_ref()
+        .map(|lang| lang.lexer())
+        .or_else(|| dialect.map(|dialect| dialect.lexer))
+        .unwrap_or(&default_lexer);
+    let converted = convert(
+        &input_text,
+        dialect_marker(&args.from),
+        dialect_marker(&args.to),
+        comment,
+        lexer,
+    );
+    write_output(args.output.as_deref(), &converted)
+}
+
+/// `equals languages`: one line per language, in the order names are
+/// resolved.
+fn list_languages() -> io::Result<()> {
+    let registry = Registry::new(&config_for(None)?).with_plugins();
+    for entry in registry.entries() {
+        let origin = match &entry.origin {
+            Origin::BuiltIn => "built-in".to_string(),
+            Origin::Config => "config".to_string(),
+            Origin::Plugin(path) => format!("plugin {}", path.display()),
+        };
+        let mut line = format!("{} ({origin})", entry.name);
+        for (label, items) in [
+            ("aliases", &entry.aliases),
+            ("extensions", &entry.extensions),
+            ("fences", &entry.fences),
+        ] {
+            if !items.is_empty() {
+                line.push_str(&format!("; {label}: {}", items.join(", ")));
+            }
+        }
+        println!("{line}");
+    }
     Ok(())
 }
 
-fn guess_language_from_path(path: &Path) -> Option<&'static str> {
-    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
-    match ext.as_str() {
-        "py" | "pyw" => Some("python"),
-        "nbt" | "nb" => Some("numbat"),
-        "fend" | "fd" => Some("fend"),
-        _ => None,
+/// Maps an alias to its language's name; unknown names are kept, since a
+/// plugin may provide them.
+fn canonical_name(registry: &Registry, name: String) -> String {
+    registry.resolve(&name).map(str::to_string).unwrap_or(name)
+}
+
+/// Splits `--marker` values into the marker for every language and those
+/// for single languages. `LANG=MARKER` only names a language when `LANG` is
+/// a plain word, so markers such as `//=` and `--=` stay whole.
+fn marker_args(values: &[String], registry: &Registry) -> (Option<String>, Vec<(String, String)>) {
+    let mut marker = None;
+    let mut language_markers = Vec::new();
+    for value in values {
+        match value.split_once('=') {
+            Some((name, language_marker))
+                if !name.is_empty()
+                    && !language_marker.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '_' || c == '-') =>
+            {
+                let name = canonical_name(registry, name.to_string());
+                language_markers.push((name, dialect_marker(language_marker).to_string()));
+            }
+            _ => marker = Some(dialect_marker(value).to_string()),
+        }
+    }
+    (marker, language_markers)
+}
+
+/// The input file, or the path stdin stands in for, which decides the
+/// parser and language.
+fn source_path(args: &Args) -> Option<&str> {
+    args.input.as_deref().or(args.stdin_filename.as_deref())
+}
+
+/// The `equals.toml` nearest to the input file, or to the working
+/// directory when reading stdin.
+fn config_for(input: Option<&str>) -> io::Result<Config> {
+    let dir = match input.and_then(|path| Path::new(path).parent()) {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => std::env::current_dir()?,
+    };
+    load_config(&dir).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn read_input(path: Option<&str>) -> io::Result<String> {
+    if let Some(path) = path {
+        fs::read_to_string(path)
+    } else {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+fn write_output(path: Option<&str>, text: &str) -> io::Result<()> {
+    if let Some(path) = path {
+        fs::write(path, text)
+    } else {
+        print!("{text}");
+        Ok(())
     }
 }
 
+/// The language of a `#!` interpreter, ignoring a version suffix such as
+/// the `.11` of `python3.11`.
+fn guess_language_from_shebang<'r>(contents: &str, registry: &'r Registry) -> Option<&'r str> {
+    let interpreter = shebang_interpreter(contents)?;
+    registry.resolve(interpreter).or_else(|| {
+        registry.resolve(interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+    })
+}
+
+fn guess_language_from_path<'r>(path: &Path, registry: &'r Registry) -> Option<&'r str> {
+    registry.for_extension(path.extension()?.to_str()?)
+}
+
 fn is_markdown_path(path: &str) -> bool {
+    has_extension(path, &["md", "markdown", "mdown", "mkd", "qmd", "rmd"])
+}
+
+fn is_org_path(path: &str) -> bool {
+    has_extension(path, &["org"])
+}
+
+fn is_asciidoc_path(path: &str) -> bool {
+    has_extension(path, &["adoc", "asciidoc"])
+}
+
+fn is_rst_path(path: &str) -> bool {
+    has_extension(path, &["rst", "rest"])
+}
+
+fn is_latex_path(path: &str) -> bool {
+    has_extension(path, &["tex", "ltx"])
+}
+
+fn is_typst_path(path: &str) -> bool {
+    has_extension(path, &["typ"])
+}
+
+fn is_notebook_path(path: &str) -> bool {
+    has_extension(path, &["ipynb"])
+}
+
+/// Source files in other languages are evaluated in comment mode, unless a
+/// language of `registry` owns their extension.
+fn comment_syntax_for_path(
+    path: &str,
+    config: &Config,
+    registry: &Registry,
+) -> Option<CommentSyntax> {
+    let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    if registry.for_extension(&ext).is_some() {
+        return None;
+    }
+    CommentSyntax::for_extension(&ext, config)
+}
+
+fn has_extension(path: &str, choices: &[&str]) -> bool {
     Path::new(path)
         .extension()
         .and_then(|os| os.to_str())
-        .map(|ext| matches_ignore_case(ext, &["md", "markdown", "mdown", "mkd"]))
+        .map(|ext| matches_ignore_case(ext, choices))
         .unwrap_or(false)
 }
 
@@ -112,24 +706,18 @@ fn matches_ignore_case(candidate: &str, choices: &[&str]) -> bool {
     choices.iter().any(|c| lower == *c)
 }
 
-fn detect_markdown_language(contents: &str) -> Option<&'static str> {
+fn detect_markdown_language<'r>(contents: &str, registry: &'r Registry) -> Option<&'r str> {
     for line in contents.lines() {
         let trimmed = line.trim_start();
         if let Some(rest) = trimmed.strip_prefix("```") {
+            // Quarto / R Markdown chunk headers: ```{python, echo=FALSE}
+            let rest = rest.trim_start().trim_start_matches(['{', '.']);
             let ident = rest
                 .chars()
                 .take_while(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
-                .collect::<String>()
-                .to_ascii_lowercase();
-            if ident.is_empty() {
-                continue;
-            }
-            match ident.as_str() {
-                "python" => return Some("python"),
-                "py" => return Some("python"),
-                "numbat" => return Some("numbat"),
-                "fend" => return Some("fend"),
-                _ => continue,
+                .collect::<String>();
+            if let Some(language) = registry.for_fence(&ident) {
+                return Some(language);
             }
         }
     }
@@ -140,14 +728,18 @@ fn detect_markdown_language(contents: &str) -> Option<&'static str> {
 mod tests {
     use super::*;
 
+    fn builtins() -> Registry {
+        Registry::new(&Config::default())
+    }
+
     #[test]
     fn detect_python_extension() {
         assert_eq!(
-            guess_language_from_path(Path::new("script.py")),
+            guess_language_from_path(Path::new("script.py"), &builtins()),
             Some("python")
         );
         assert_eq!(
-            guess_language_from_path(Path::new("script.PYW")),
+            guess_language_from_path(Path::new("script.PYW"), &builtins()),
             Some("python")
         );
     }
@@ -155,11 +747,11 @@ mod tests {
     #[test]
     fn detect_numbat_extension() {
         assert_eq!(
-            guess_language_from_path(Path::new("calc.nbt")),
+            guess_language_from_path(Path::new("calc.nbt"), &builtins()),
             Some("numbat")
         );
         assert_eq!(
-            guess_language_from_path(Path::new("calc.NB")),
+            guess_language_from_path(Path::new("calc.NB"), &builtins()),
             Some("numbat")
         );
     }
@@ -167,18 +759,21 @@ mod tests {
     #[test]
     fn detect_fend_extension() {
         assert_eq!(
-            guess_language_from_path(Path::new("notes.fend")),
+            guess_language_from_path(Path::new("notes.fend"), &builtins()),
             Some("fend")
         );
         assert_eq!(
-            guess_language_from_path(Path::new("notes.FD")),
+            guess_language_from_path(Path::new("notes.FD"), &builtins()),
             Some("fend")
         );
     }
 
     #[test]
     fn unknown_extension_returns_none() {
-        assert_eq!(guess_language_from_path(Path::new("notes.txt")), None);
+        assert_eq!(
+            guess_language_from_path(Path::new("notes.txt"), &builtins()),
+            None
+        );
     }
 
     #[test]
@@ -186,9 +781,108 @@ mod tests {
         assert!(is_markdown_path("guide.md"));
         assert!(is_markdown_path("guide.MarkDown"));
         assert!(is_markdown_path("notes.mdown"));
+        assert!(is_markdown_path("report.qmd"));
+        assert!(is_markdown_path("report.Rmd"));
         assert!(!is_markdown_path("script.py"));
     }
 
+    #[test]
+    fn detects_org_path() {
+        assert!(is_org_path("notes.org"));
+        assert!(is_org_path("NOTES.ORG"));
+        assert!(!is_org_path("notes.md"));
+    }
+
+    #[test]
+    fn detects_asciidoc_paths() {
+        assert!(is_asciidoc_path("manual.adoc"));
+        assert!(is_asciidoc_path("manual.AsciiDoc"));
+        assert!(!is_asciidoc_path("manual.txt"));
+    }
+
+    #[test]
+    fn detects_rst_paths() {
+        assert!(is_rst_path("index.rst"));
+        assert!(is_rst_path("index.REST"));
+        assert!(!is_rst_path("index.md"));
+    }
+
+    #[test]
+    fn detects_latex_paths() {
+        assert!(is_latex_path("report.tex"));
+        assert!(is_latex_path("report.LTX"));
+        assert!(!is_latex_path("report.pdf"));
+    }
+
+    #[test]
+    fn source_files_use_comment_mode() {
+        let config = Config::default();
+        let registry = builtins();
+        assert!(comment_syntax_for_path("src/main.rs", &config, &registry).is_some());
+        assert!(comment_syntax_for_path("query.SQL", &config, &registry).is_some());
+        assert!(comment_syntax_for_path("script.py", &config, &registry).is_none());
+        assert!(comment_syntax_for_path("notes.md", &config, &registry).is_none());
+    }
+
+    #[test]
+    fn config_languages_take_over_comment_mode_extensions() {
+        let dir = std::env::temp_dir().join(format!("equals-rb-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(crate::config::CONFIG_FILE),
+            r#"
+[languages.rb]
+command = "python3"
+args = ["-"]
+print = 'print("{sentinel}", {expr})'
+extensions = ["rb"]
+"#,
+        )
+        .unwrap();
+        let path = dir.join("a.rb");
+        let text = "x = 6\nx * 7 #=\n# 1 + 1 #=";
+        fs::write(&path, text).unwrap();
+
+        let args = Args::parse_from(["equals", "--input", path.to_str().unwrap()]);
+        let output = evaluate_text(&args, text, &mut Backends::default());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(output.unwrap(), "x = 6\nx * 7 #= 42\n# 1 + 1 #=");
+    }
+
+    #[test]
+    fn dialect_markers_evaluate_the_code_they_annotate() {
+        let dir = std::env::temp_dir().join(format!("equals-dialects-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let evaluate = |file: &str, marker: &str, text: &str| {
+            let path = dir.join(file);
+            let path = path.to_str().unwrap();
+            let args = Args::parse_from([
+                "equals", "--input", path, "--marker", marker, "-l", "python",
+            ]);
+            evaluate_text(&args, text, &mut Backends::default()).unwrap()
+        };
+
+        let ruby = evaluate("a.rb", "xmpfilter", "x = 6\nx * 7 # =>\n# prose");
+        let js = evaluate("a.js", "quokka", "x = 6\nx * 7 // =>");
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(ruby, "x = 6\nx * 7 # => 42\n# prose");
+        assert_eq!(js, "x = 6\nx * 7 // => 42");
+    }
+
+    #[test]
+    fn detects_typst_paths() {
+        assert!(is_typst_path("report.typ"));
+        assert!(is_typst_path("report.TYP"));
+        assert!(!is_typst_path("report.type"));
+    }
+
+    #[test]
+    fn detects_notebook_paths() {
+        assert!(is_notebook_path("analysis.ipynb"));
+        assert!(is_notebook_path("analysis.IPYNB"));
+        assert!(!is_notebook_path("analysis.json"));
+    }
+
     #[test]
     fn detect_markdown_language_from_fence() {
         let doc = r#"
@@ -197,7 +891,7 @@ Some text
 print("hi")
 ```
 "#;
-        assert_eq!(detect_markdown_language(doc), Some("python"));
+        assert_eq!(detect_markdown_language(doc, &builtins()), Some("python"));
     }
 
     #[test]
@@ -213,7 +907,13 @@ hi
 let x = 2
 ```
 "#;
-        assert_eq!(detect_markdown_language(doc), Some("numbat"));
+        assert_eq!(detect_markdown_language(doc, &builtins()), Some("numbat"));
+    }
+
+    #[test]
+    fn detect_markdown_language_from_chunk_header() {
+        let doc = "```{r setup}\nlibrary(x)\n```\n```{fend, echo=FALSE}\n1 + 1\n```";
+        assert_eq!(detect_markdown_language(doc, &builtins()), Some("fend"));
     }
 
     #[test]
@@ -224,7 +924,7 @@ Some intro
 x = 1
 ```
 "#;
-        assert_eq!(detect_markdown_language(doc), Some("python"));
+        assert_eq!(detect_markdown_language(doc, &builtins()), Some("python"));
     }
 
     #[test]
@@ -237,7 +937,7 @@ pass
 let x = 2
 ```
 "#;
-        assert_eq!(detect_markdown_language(doc), Some("python"));
+        assert_eq!(detect_markdown_language(doc, &builtins()), Some("python"));
     }
 
     #[test]
@@ -247,6 +947,59 @@ let x = 2
     2 + 2
     ```
 "#;
-        assert_eq!(detect_markdown_language(doc), Some("fend"));
+        assert_eq!(detect_markdown_language(doc, &builtins()), Some("fend"));
+    }
+
+    #[test]
+    fn guess_language_from_shebang_lines() {
+        let registry = builtins();
+        let guess = |text| guess_language_from_shebang(text, &registry);
+        assert_eq!(guess("#!/usr/bin/env python3\nx = 1"), Some("python"));
+        assert_eq!(guess("#!/usr/bin/python3.11"), Some("python"));
+        assert_eq!(guess("#!/usr/bin/env numbat"), Some("numbat"));
+        assert_eq!(guess("#!/bin/sh"), None);
+        assert_eq!(guess("x = 1"), None);
+    }
+
+    #[test]
+    fn marker_args_split_global_and_language_markers() {
+        let values: Vec<String> = ["//=", "nb=%=", "--=", "lua=--=", "ruby=xmpfilter"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let (marker, language_markers) = marker_args(&values, &builtins());
+
+        assert_eq!(marker.as_deref(), Some("--="));
+        assert_eq!(
+            language_markers,
+            vec![
+                ("numbat".to_string(), "%=".to_string()),
+                ("lua".to_string(), "--=".to_string()),
+                ("ruby".to_string(), "# =>".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn detection_follows_the_registry() {
+        assert_eq!(
+            detect_markdown_language("```nbt\n1\n```", &builtins()),
+            Some("numbat")
+        );
+        let config = config::parse_config(
+            "[languages.node]\ncommand = \"node\"\nprint = 'console.log(\"{sentinel}\", {expr})'\nextensions = [\"js\"]\nfences = [\"javascript\"]",
+        )
+        .unwrap();
+        let registry = Registry::new(&config);
+        assert_eq!(
+            guess_language_from_path(Path::new("app.js"), &registry),
+            Some("node")
+        );
+        assert_eq!(
+            detect_markdown_language("```javascript\n1\n```", &registry),
+            Some("node")
+        );
+        assert_eq!(canonical_name(&registry, "py".into()), "python");
+        assert_eq!(canonical_name(&registry, "dsl".into()), "dsl");
     }
 }
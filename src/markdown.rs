@@ -1,43 +1,415 @@
 use crate::{
-    document::{Block, Document, Line},
+    document::{Block, Document, Line, OutputFence, Session},
+    frontmatter::{FrontMatter, SessionMode},
+    lang::registry::Registry,
     parser::Parser,
+    table::Table,
 };
 
-pub struct MarkdownParser;
+/// Settings read from an opening fence's info string.
+struct FenceInfo<'a> {
+    language: Option<&'a str>,
+    eval: bool,
+    /// `output=true`: capture what the block prints into an output block.
+    output: bool,
+}
+
+pub struct MarkdownParser {
+    /// Only evaluate fenced code blocks; used for doc comments, where inline
+    /// spans name types and functions rather than expressions.
+    fenced_only: bool,
+    /// Lines taken by the document's front matter, kept as text.
+    front_matter_lines: usize,
+    sessions: SessionMode,
+    /// Resolves fence tags and language prefixes.
+    registry: Registry,
+}
+
+/// Tracks where the parser is while walking a document line by line.
+struct ParseState {
+    in_code_block: bool,
+    /// The open fenced block is kept as text (`eval=FALSE`, unknown engine).
+    skip_block: bool,
+    /// Inside a `<pre><code>` HTML block.
+    in_html_block: bool,
+    /// The open HTML block escapes characters as entities (`&lt;`), which
+    /// interpreters can't read, so it's kept as text.
+    skip_html_block: bool,
+    /// Inside a block indented by four spaces (or a tab).
+    in_indented_block: bool,
+    /// The previous line was blank; indented code can't interrupt a paragraph.
+    prev_blank: bool,
+    /// Indented lines after a list item continue the item, not code.
+    in_list: bool,
+    sessions: SessionMode,
+    /// Session of the code block currently open.
+    block_session: Session,
+    /// Counter used to name isolated sessions.
+    next_session: usize,
+    /// Fences tagged `{output=true}`; the last one may still be open.
+    outputs: Vec<OutputFence>,
+    output_open: bool,
+}
+
+impl Default for ParseState {
+    fn default() -> Self {
+        Self {
+            in_code_block: false,
+            skip_block: false,
+            in_html_block: false,
+            skip_html_block: false,
+            in_indented_block: false,
+            prev_blank: true,
+            in_list: false,
+            sessions: SessionMode::default(),
+            block_session: Session::default(),
+            next_session: 0,
+            outputs: Vec::new(),
+            output_open: false,
+        }
+    }
+}
+
+impl ParseState {
+    fn new_session(&mut self) -> Session {
+        match self.sessions {
+            SessionMode::Shared => Session::default(),
+            SessionMode::Isolated => {
+                self.next_session += 1;
+                Session::named(format!("block-{}", self.next_session))
+            }
+        }
+    }
+}
 
 impl MarkdownParser {
     pub fn new() -> Self {
-        Self
+        Self {
+            fenced_only: false,
+            front_matter_lines: 0,
+            sessions: SessionMode::default(),
+            registry: Registry::default(),
+        }
+    }
+
+    pub fn fenced_only() -> Self {
+        Self {
+            fenced_only: true,
+            ..Self::new()
+        }
+    }
+
+    /// Applies the document's already parsed front matter.
+    pub fn with_front_matter(mut self, front_matter: Option<&FrontMatter>) -> Self {
+        if let Some(front_matter) = front_matter {
+            self.front_matter_lines = front_matter.line_count;
+            self.sessions = front_matter.sessions;
+        }
+        self
     }
 
-    pub fn parse_line(&self, number: usize, line: &str, in_code_block: &mut bool) -> Line {
+    /// Resolves languages with `registry`, so config languages and plugins
+    /// are recognised, instead of the built-ins only.
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    fn parse_line(&self, lines: &[&str], idx: usize, state: &mut ParseState) -> Line {
+        let line = lines[idx];
+        if state.in_html_block {
+            return Self::parse_html_code_line(line, state);
+        }
+
         if Self::is_fence(line) {
-            *in_code_block = !*in_code_block;
-            return Line {
-                number,
-                blocks: vec![Block::Text((0, line.len()), line.to_string())],
-            };
+            state.in_code_block = !state.in_code_block;
+            if state.in_code_block {
+                let info = self.fence_info(line);
+                let session = state.new_session();
+                state.block_session = match info.language {
+                    Some(language) => session.with_language(language),
+                    None => session,
+                };
+                state.skip_block = !info.eval;
+                if info.output && info.eval {
+                    state.outputs.push(OutputFence {
+                        body: idx + 1..idx + 1,
+                        closing: idx,
+                        existing: None,
+                        text: None,
+                        prefix: String::new(),
+                    });
+                    state.output_open = true;
+                }
+            } else if state.output_open
+                && let Some(output) = state.outputs.last_mut()
+            {
+                output.body.end = idx;
+                output.closing = idx;
+                state.output_open = false;
+            }
+            return Line::text(line);
+        }
+
+        if state.in_code_block && state.skip_block {
+            return Line::text(line);
+        }
+
+        if state.in_code_block {
+            return Self::parse_fenced_code_line(line, state.block_session.clone());
+        }
+
+        if self.fenced_only {
+            return Line::text(line);
         }
 
-        if *in_code_block {
-            return Self::parse_fenced_code_line(number, line);
+        if let Some(line) = self.parse_html_code_start(&lines[idx..], state) {
+            return line;
         }
 
-        Self::parse_inline_code_line(number, line)
+        if let Some(line) = Self::parse_indented_code_line(line, state) {
+            return line;
+        }
+
+        let blank = line.trim().is_empty();
+        if Self::is_list_item(line) {
+            state.in_list = true;
+        } else if !blank && !line.starts_with([' ', '\t']) {
+            state.in_list = false;
+        }
+        state.prev_blank = blank;
+
+        self.parse_inline_code_line(line, state)
+    }
+
+    /// Builds a line whose `code` range is a code block and the rest text.
+    fn code_range_line(line: &str, code: std::ops::Range<usize>, session: Session) -> Line {
+        if code.is_empty() {
+            return Line::text(line);
+        }
+
+        let mut blocks = Vec::new();
+        if code.start > 0 {
+            blocks.push(Block::Text((0, code.start), line[..code.start].to_string()));
+        }
+        blocks.push(Block::Code(
+            (code.start, code.end),
+            line[code.clone()].to_string(),
+            session,
+        ));
+        if code.end < line.len() {
+            blocks.push(Block::Text(
+                (code.end, line.len()),
+                line[code.end..].to_string(),
+            ));
+        }
+        Line { blocks }
     }
 
     fn is_fence(line: &str) -> bool {
         line.trim_start().starts_with("```")
     }
 
-    fn parse_fenced_code_line(number: usize, line: &str) -> Line {
+    /// Reads the info string of an opening fence: a plain ```` ```python ````
+    /// (optionally followed by `{output=true}` attributes) or a Quarto / R
+    /// Markdown chunk header ```` ```{python, echo=FALSE} ````. Chunks in
+    /// other engines and blocks with `eval=FALSE` aren't evaluated.
+    fn fence_info(&self, line: &str) -> FenceInfo<'_> {
+        let info = line.trim_start().trim_start_matches('`').trim();
+
+        let (language, options): (_, Vec<&str>) = if let Some(header) = info.strip_prefix('{') {
+            let header = header.split('}').next().unwrap_or("");
+            let (first, rest) = header.split_once(',').unwrap_or((header, ""));
+            // `{r label, echo=FALSE}`: the engine may be followed by a label or,
+            // in R Markdown, by options without a comma.
+            let mut words = first.split_whitespace();
+            let engine = words.next().unwrap_or("").trim_start_matches('.');
+            let Some(language) = self.registry.for_fence(engine) else {
+                return FenceInfo {
+                    language: None,
+                    eval: false,
+                    output: false,
+                };
+            };
+            (Some(language), words.chain(rest.split(',')).collect())
+        } else {
+            let split = info
+                .find(|c: char| c.is_whitespace() || c == '{')
+                .unwrap_or(info.len());
+            let (word, attributes) = info.split_at(split);
+            let attributes = attributes
+                .trim_start()
+                .strip_prefix('{')
+                .and_then(|a| a.split('}').next())
+                .unwrap_or("");
+            (
+                self.registry.for_fence(word),
+                attributes.split([',', ' ']).collect(),
+            )
+        };
+
+        let option = |key: &str| {
+            options.iter().find_map(|option| {
+                let (k, v) = option.split_once('=')?;
+                (k.trim() == key).then(|| v.trim().trim_matches('"'))
+            })
+        };
+        FenceInfo {
+            language,
+            eval: !option("eval").is_some_and(|v| matches!(v, "FALSE" | "F" | "false" | "False")),
+            output: option("output").is_some_and(|v| matches!(v, "TRUE" | "T" | "true" | "True")),
+        }
+    }
+
+    /// Finds the ```` ```output ```` (or ```` ```text ````) block right after
+    /// the closing fence at `closing`, allowing one blank line in between.
+    fn existing_output(raw_lines: &[&str], closing: usize) -> Option<std::ops::Range<usize>> {
+        let mut start = closing + 1;
+        if raw_lines.get(start)?.trim().is_empty() {
+            start += 1;
+        }
+        let info = raw_lines.get(start)?.trim().strip_prefix("```")?;
+        if !matches!(info.trim(), "output" | "text") {
+            return None;
+        }
+        let end = (start + 1..raw_lines.len()).find(|&i| Self::is_fence(raw_lines[i]))?;
+        Some(start..end + 1)
+    }
+
+    fn is_list_item(line: &str) -> bool {
+        let trimmed = line.trim_start();
+        if line.len() - trimmed.len() >= 4 {
+            return false;
+        }
+        let rest = trimmed.trim_start_matches(|c: char| c.is_ascii_digit());
+        let rest = if rest.len() < trimmed.len() {
+            rest.strip_prefix(['.', ')'])
+        } else {
+            trimmed.strip_prefix(['-', '*', '+'])
+        };
+        rest.is_some_and(|r| r.is_empty() || r.starts_with([' ', '\t']))
+    }
+
+    fn parse_fenced_code_line(line: &str, session: Session) -> Line {
         Line {
-            number,
-            blocks: vec![Block::Code((0, line.len()), line.to_string())],
+            blocks: vec![Block::Code((0, line.len()), line.to_string(), session)],
+        }
+    }
+
+    /// Handles a line inside an indented code block, keeping the indentation
+    /// as text so it survives evaluation unchanged.
+    fn parse_indented_code_line(line: &str, state: &mut ParseState) -> Option<Line> {
+        let indent = if line.starts_with('\t') {
+            1
+        } else if line.starts_with("    ") {
+            4
+        } else {
+            0
+        };
+
+        if indent == 0 || line.trim().is_empty() {
+            // Blank lines don't end an indented block; anything else does.
+            if !(state.in_indented_block && line.trim().is_empty()) {
+                state.in_indented_block = false;
+            }
+            return None;
         }
+
+        if !state.in_indented_block {
+            if !state.prev_blank || state.in_list {
+                return None;
+            }
+            state.in_indented_block = true;
+            state.block_session = state.new_session();
+        }
+
+        Some(Self::code_range_line(
+            line,
+            indent..line.len(),
+            state.block_session.clone(),
+        ))
     }
 
-    fn parse_inline_code_line(number: usize, line: &str) -> Line {
+    /// Opens a `<pre><code>` block, taking the language from a
+    /// `class="language-xyz"` (or `lang-xyz`) attribute when present.
+    /// `lines` starts at the opening line and runs to the end of the input.
+    fn parse_html_code_start(&self, lines: &[&str], state: &mut ParseState) -> Option<Line> {
+        let line = lines[0];
+        let trimmed = line.trim_start();
+        if !trimmed.starts_with("<pre") {
+            return None;
+        }
+        let code_tag = line.find("<code")?;
+        let open_end = code_tag + line[code_tag..].find('>')? + 1;
+
+        let mut session = state.new_session();
+        if let Some(language) = self.html_class_language(&line[code_tag..open_end]) {
+            session = session.with_language(language);
+        }
+        state.block_session = session;
+        state.in_html_block = true;
+        state.skip_html_block = Self::html_block_has_entities(&line[open_end..], &lines[1..]);
+
+        if open_end == line.len() {
+            return Some(Line::text(line));
+        }
+        let mut rest = Self::parse_html_code_line(&line[open_end..], state);
+        let mut blocks = vec![Block::Text((0, open_end), line[..open_end].to_string())];
+        for block in rest.blocks.drain(..) {
+            blocks.push(match block {
+                Block::Text((s, e), t) => Block::Text((s + open_end, e + open_end), t),
+                Block::Code((s, e), c, session) => {
+                    Block::Code((s + open_end, e + open_end), c, session)
+                }
+            });
+        }
+        Some(Line { blocks })
+    }
+
+    fn parse_html_code_line(line: &str, state: &mut ParseState) -> Line {
+        let code_end = match line.find("</code>") {
+            Some(pos) => {
+                state.in_html_block = false;
+                pos
+            }
+            None => line.len(),
+        };
+        if state.skip_html_block {
+            return Line::text(line);
+        }
+        Self::code_range_line(line, 0..code_end, state.block_session.clone())
+    }
+
+    /// Whether the code of an HTML block, from `first` (the rest of the
+    /// opening line) to the `</code>` in `following`, holds an entity.
+    fn html_block_has_entities(first: &str, following: &[&str]) -> bool {
+        let mut code = Vec::new();
+        for line in std::iter::once(first).chain(following.iter().copied()) {
+            match line.find("</code>") {
+                Some(end) => {
+                    code.push(&line[..end]);
+                    break;
+                }
+                None => code.push(line),
+            }
+        }
+        code.iter().any(|line| has_html_entity(line))
+    }
+
+    fn html_class_language(&self, tag: &str) -> Option<&str> {
+        let (_, rest) = tag.split_once("class=")?;
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let classes = rest[1..].split(quote).next()?;
+        classes.split_whitespace().find_map(|class| {
+            let name = class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("lang-"))
+                .unwrap_or(class);
+            self.registry.for_fence(name)
+        })
+    }
+
+    fn parse_inline_code_line(&self, line: &str, state: &mut ParseState) -> Line {
         let mut blocks = Vec::new();
         let mut text_buf = String::new();
         let mut code_buf = String::new();
@@ -50,7 +422,21 @@ impl MarkdownParser {
                     // End of inline code
                     let start = col.saturating_sub(code_buf.len());
                     let end = col;
-                    blocks.push(Block::Code((start, end), code_buf.clone()));
+                    let mut session = state.new_session();
+                    let mut code_start = start;
+                    if let Some((prefix_len, language)) = self.split_language_prefix(&code_buf) {
+                        code_start += prefix_len;
+                        session = session.with_language(language);
+                        blocks.push(Block::Text(
+                            (start, code_start),
+                            code_buf[..prefix_len].to_string(),
+                        ));
+                    }
+                    blocks.push(Block::Code(
+                        (code_start, end),
+                        code_buf[code_start - start..].to_string(),
+                        session,
+                    ));
                     code_buf.clear();
                     inside_inline = false;
                     text_buf.push('`'); // preserve closing backtick
@@ -73,7 +459,30 @@ impl MarkdownParser {
             col += 1;
         }
 
-        Self::flush_inline_buffers(blocks, text_buf, code_buf, inside_inline, col, number)
+        Self::flush_inline_buffers(blocks, text_buf, code_buf, inside_inline, col)
+    }
+
+    /// Recognises an explicit language prefix at the start of an inline span,
+    /// either `nbt: ...` or `{fend} ...`. Returns the prefix length (including
+    /// the whitespace after it) and the canonical language name.
+    fn split_language_prefix(&self, code: &str) -> Option<(usize, &str)> {
+        let (alias, rest) = if let Some(inner) = code.strip_prefix('{') {
+            let (alias, rest) = inner.split_once('}')?;
+            (alias, rest)
+        } else {
+            let (alias, rest) = code.split_once(':')?;
+            if !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            (alias, rest)
+        };
+
+        if alias.is_empty() || !alias.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let language = self.registry.for_fence(alias)?;
+        let body = rest.trim_start();
+        Some((code.len() - body.len(), language))
     }
 
     fn flush_inline_buffers(
@@ -82,14 +491,13 @@ impl MarkdownParser {
         code_buf: String,
         inside_inline: bool,
         col: usize,
-        number: usize,
     ) -> Line {
         if inside_inline {
             // Unclosed inline code → merge all into text
             let mut full_text = String::new();
             for block in blocks.drain(..) {
                 match block {
-                    Block::Text(_, t) | Block::Code(_, t) => full_text.push_str(&t),
+                    Block::Text(_, t) | Block::Code(_, t, _) => full_text.push_str(&t),
                 }
             }
             full_text.push_str(&text_buf);
@@ -101,44 +509,102 @@ impl MarkdownParser {
             blocks.push(Block::Text((start, end), text_buf));
         }
 
-        Line { number, blocks }
+        Line { blocks }
     }
 }
 
+/// Whether `text` holds a character reference like `&lt;`, `&#60;` or
+/// `&#x3C;`.
+fn has_html_entity(text: &str) -> bool {
+    text.match_indices('&').any(|(pos, _)| {
+        let rest = &text[pos + 1..];
+        let Some(end) = rest.find(';') else {
+            return false;
+        };
+        let name = &rest[..end];
+        let name = name
+            .strip_prefix("#x")
+            .or_else(|| name.strip_prefix("#X"))
+            .or_else(|| name.strip_prefix('#'))
+            .unwrap_or(name);
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
 impl Parser for MarkdownParser {
     fn parse(&self, input: &str) -> Document {
         let mut lines = Vec::new();
-        let mut in_code_block = false;
+        let mut state = ParseState {
+            sessions: self.sessions,
+            ..ParseState::default()
+        };
+
+        let raw_lines: Vec<&str> = input
+            .split_inclusive('\n')
+            .map(|line| line.strip_suffix('\n').unwrap_or(line))
+            .collect();
+        let mut tables: Vec<Table> = Vec::new();
+
+        for (i, line) in raw_lines.iter().enumerate() {
+            if i < self.front_matter_lines {
+                lines.push(Line::text(line));
+                continue;
+            }
 
-        for (i, raw_line) in input.split_inclusive('\n').enumerate() {
-            let number = i + 1;
-            let mut line = raw_line.to_string();
-            if line.ends_with('\n') {
-                line.pop();
+            let in_table = tables.last().is_some_and(|t| t.lines.contains(&i));
+            if !self.fenced_only
+                && !state.in_code_block
+                && !in_table
+                && let Some(table) = Table::detect(&raw_lines, i)
+            {
+                tables.push(table);
             }
-            lines.push(self.parse_line(number, &line, &mut in_code_block));
+            lines.push(self.parse_line(&raw_lines, i, &mut state));
         }
 
-        Document { lines }
+        if state.output_open {
+            state.outputs.pop();
+        }
+        for output in &mut state.outputs {
+            output.existing = Self::existing_output(&raw_lines, output.closing);
+            // The old output is replaced, never evaluated.
+            for idx in output.existing.clone().into_iter().flatten() {
+                lines[idx] = Line::text(raw_lines[idx]);
+            }
+        }
+
+        Document {
+            lines,
+            tables,
+            outputs: state.outputs,
+            errors: Vec::new(),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::document::Block;
+    use crate::document::{Block, CodeBlockUpdate};
+    use crate::frontmatter::parse_front_matter;
 
     fn mk_parser() -> MarkdownParser {
         MarkdownParser::new()
     }
 
+    fn parse_with_front_matter(src: &str) -> Document {
+        mk_parser()
+            .with_front_matter(parse_front_matter(src).as_ref())
+            .parse(src)
+    }
+
     fn assert_text_block_eq(block: &Block, expected: &str) {
         match block {
             Block::Text(_, actual) => assert_eq!(
                 actual, expected,
                 "❌ Expected text block `{expected}`, got `{actual}`"
             ),
-            Block::Code(_, actual) => {
+            Block::Code(_, actual, _) => {
                 panic!("❌ Expected text block `{expected}`, but found CODE block `{actual}`")
             }
         }
@@ -146,7 +612,7 @@ mod tests {
 
     fn assert_code_block_eq(block: &Block, expected: &str) {
         match block {
-            Block::Code(_, actual) => assert_eq!(
+            Block::Code(_, actual, _) => assert_eq!(
                 actual, expected,
                 "❌ Expected code block `{expected}`, got `{actual}`"
             ),
@@ -246,6 +712,56 @@ mod tests {
         assert_text_block_eq(&lines[2].blocks[0], "```");
     }
 
+    #[test]
+    fn fence_info_string_sets_block_language() {
+        let src = "```numbat\nlet x = 2 m\n```\n```\n1 + 1\n```";
+        let doc = mk_parser().parse(src);
+
+        let languages: Vec<_> = doc
+            .lines
+            .iter()
+            .flat_map(|line| &line.blocks)
+            .filter_map(|block| match block {
+                Block::Code(_, _, session) => Some(session.language.as_deref()),
+                Block::Text(..) => None,
+            })
+            .collect();
+        assert_eq!(languages, vec![Some("numbat"), None]);
+    }
+
+    #[test]
+    fn chunk_headers_pick_language_and_options() {
+        let src = "\
+```{python}
+x = 2
+```
+```{python, echo=FALSE}
+x * 3 #=
+```
+```{python eval=FALSE}
+skipped #=
+```
+```{r setup, include=FALSE}
+library(knitr)
+```";
+        let doc = mk_parser().parse(src);
+
+        let codes: Vec<_> = doc
+            .lines
+            .iter()
+            .flat_map(|line| &line.blocks)
+            .filter_map(|block| match block {
+                Block::Code(_, code, session) => Some((code.as_str(), session.language.as_deref())),
+                Block::Text(..) => None,
+            })
+            .collect();
+        assert_eq!(
+            codes,
+            vec![("x = 2", Some("python")), ("x * 3 #=", Some("python"))]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
     #[test]
     fn reconstruct_roundtrip_inline_and_fenced() {
         let src = r#"
@@ -265,4 +781,269 @@ Inline `2 + 2` works."#;
             reconstructed
         );
     }
+
+    #[test]
+    fn front_matter_is_kept_as_text() {
+        let src = "---\nequals: { marker: \"`#=`\" }\n---\n`1 #=`";
+        let doc = parse_with_front_matter(src);
+
+        assert_eq!(doc.lines.len(), 4);
+        for line in &doc.lines[..3] {
+            assert_eq!(line.blocks.len(), 1);
+        }
+        assert_text_block_eq(&doc.lines[1].blocks[0], "equals: { marker: \"`#=`\" }");
+        assert_code_block_eq(&doc.lines[3].blocks[1], "1 #=");
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn isolated_sessions_give_each_block_its_own_session() {
+        let src = "---\nequals: { sessions: isolated }\n---\n```\na\nb\n```\n```\nc\n```\n`d`";
+        let doc = parse_with_front_matter(src);
+
+        let sessions: Vec<Option<String>> = doc
+            .lines
+            .iter()
+            .flat_map(|line| &line.blocks)
+            .filter_map(|block| match block {
+                Block::Code(_, _, session) => Some(session.name.clone()),
+                Block::Text(..) => None,
+            })
+            .collect();
+
+        assert_eq!(
+            sessions,
+            vec![
+                Some("block-1".into()),
+                Some("block-1".into()),
+                Some("block-2".into()),
+                Some("block-3".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_language_prefix_is_kept_as_text() {
+        let doc = mk_parser().parse("Convert `nbt: 3 ft -> cm #=` or `{fend} 1 mile in km #=`.");
+        let blocks = &doc.lines[0].blocks;
+
+        let expected = vec![
+            ("text", "Convert `"),
+            ("text", "nbt: "),
+            ("code", "3 ft -> cm #="),
+            ("text", "` or `"),
+            ("text", "{fend} "),
+            ("code", "1 mile in km #="),
+            ("text", "`."),
+        ];
+
+        assert_eq!(blocks.len(), expected.len());
+        for (block, (kind, content)) in blocks.iter().zip(expected) {
+            match kind {
+                "text" => assert_text_block_eq(block, content),
+                "code" => assert_code_block_eq(block, content),
+                _ => unreachable!(),
+            }
+        }
+
+        let languages: Vec<Option<&str>> = blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Code(_, _, session) => Some(session.language.as_deref()),
+                Block::Text(..) => None,
+            })
+            .collect();
+        assert_eq!(languages, vec![Some("numbat"), Some("fend")]);
+        assert_eq!(
+            doc.reconstruct(),
+            "Convert `nbt: 3 ft -> cm #=` or `{fend} 1 mile in km #=`."
+        );
+    }
+
+    #[test]
+    fn unknown_prefixes_stay_in_the_code() {
+        let doc = mk_parser().parse("`a: int = 3 #=` and `{x} 1`");
+        let blocks = &doc.lines[0].blocks;
+
+        assert_code_block_eq(&blocks[1], "a: int = 3 #=");
+        assert_code_block_eq(&blocks[3], "{x} 1");
+    }
+
+    #[test]
+    fn tables_are_recorded_outside_fences() {
+        let src = "```\n| a | b |\n|---|---|\n```\n\n| a | b |\n|---|---|\n| `1 #=` | x |";
+        let doc = mk_parser().parse(src);
+
+        assert_eq!(doc.tables.len(), 1);
+        assert_eq!(doc.tables[0].lines, 5..8);
+        assert_code_block_eq(&doc.lines[7].blocks[1], "1 #=");
+    }
+
+    #[test]
+    fn evaluated_table_cells_keep_columns_aligned() {
+        let src = "\
+| Item | Cost          |
+| ---- | ------------: |
+| Tea  | `2 * 3 #= 6`  |
+| Cake | `a * b #=`    |";
+        let mut doc = mk_parser().parse(src);
+        doc.evaluate_with(|_, blocks| {
+            vec![CodeBlockUpdate {
+                id: blocks[1].id,
+                content: "a * b #= 1250.5".into(),
+            }]
+        });
+
+        assert_eq!(
+            doc.reconstruct(),
+            "\
+| Item |              Cost |
+| ---- | ----------------: |
+| Tea  |      `2 * 3 #= 6` |
+| Cake | `a * b #= 1250.5` |"
+        );
+    }
+
+    #[test]
+    fn indented_code_block_keeps_indentation_as_text() {
+        let src = "Intro paragraph.\n\n    x = 1\n\n    x + 1 #=\nAfter.";
+        let doc = mk_parser().parse(src);
+
+        assert_text_block_eq(&doc.lines[2].blocks[0], "    ");
+        assert_code_block_eq(&doc.lines[2].blocks[1], "x = 1");
+        assert!(doc.lines[3].blocks.is_empty());
+        assert_text_block_eq(&doc.lines[4].blocks[0], "    ");
+        assert_code_block_eq(&doc.lines[4].blocks[1], "x + 1 #=");
+        assert_text_block_eq(&doc.lines[5].blocks[0], "After.");
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn indented_lines_after_paragraphs_or_lists_are_text() {
+        let src = "Paragraph\n    continued\n\n- item\n\n    more item text";
+        let doc = mk_parser().parse(src);
+
+        for line in &doc.lines {
+            assert!(line.blocks.iter().all(|b| matches!(b, Block::Text(..))));
+        }
+    }
+
+    #[test]
+    fn html_pre_code_block_uses_class_language() {
+        let src = "<pre><code class=\"language-numbat\">let x = 2 m\nx * 3 #=</code></pre>\n`1 #=`";
+        let doc = mk_parser().parse(src);
+
+        let blocks = &doc.lines[0].blocks;
+        assert_text_block_eq(&blocks[0], "<pre><code class=\"language-numbat\">");
+        assert_code_block_eq(&blocks[1], "let x = 2 m");
+        assert_code_block_eq(&doc.lines[1].blocks[0], "x * 3 #=");
+        assert_text_block_eq(&doc.lines[1].blocks[1], "</code></pre>");
+
+        let languages: Vec<Option<&str>> = doc
+            .lines
+            .iter()
+            .flat_map(|line| &line.blocks)
+            .filter_map(|block| match block {
+                Block::Code(_, _, session) => Some(session.language.as_deref()),
+                Block::Text(..) => None,
+            })
+            .collect();
+        assert_eq!(languages, vec![Some("numbat"), Some("numbat"), None]);
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn html_pre_code_with_entities_is_kept_as_text() {
+        let src = "<pre><code>x = 1\nx &lt; 2 #=\n</code></pre>\n<pre><code>1 + 1 #=</code></pre>";
+        let doc = mk_parser().parse(src);
+
+        for line in &doc.lines[..3] {
+            assert!(line.blocks.iter().all(|b| matches!(b, Block::Text(..))));
+        }
+        assert_code_block_eq(&doc.lines[3].blocks[1], "1 + 1 #=");
+        assert_eq!(doc.reconstruct(), src);
+        assert!(has_html_entity("a &amp;&amp; b"));
+        assert!(has_html_entity("&#x3C;"));
+        assert!(!has_html_entity("a & b; c"));
+    }
+
+    #[test]
+    fn html_pre_code_on_its_own_lines() {
+        let src = "<pre><code>\n2 + 2 #=\n</code></pre>";
+        let doc = mk_parser().parse(src);
+
+        assert_eq!(doc.lines[0].blocks.len(), 1);
+        assert_text_block_eq(&doc.lines[0].blocks[0], "<pre><code>");
+        assert_code_block_eq(&doc.lines[1].blocks[0], "2 + 2 #=");
+        assert_text_block_eq(&doc.lines[2].blocks[0], "</code></pre>");
+    }
+
+    #[test]
+    fn output_fences_find_existing_output_blocks() {
+        let src = "\
+```python {output=true}
+print(1)
+```
+
+```output
+stale #=
+```
+```{python, output=TRUE}
+print(2)
+```
+```python
+print(3)
+```";
+        let doc = mk_parser().parse(src);
+
+        assert_eq!(
+            doc.outputs,
+            vec![
+                OutputFence {
+                    body: 1..2,
+                    closing: 2,
+                    existing: Some(4..7),
+                    text: None,
+                    prefix: String::new(),
+                },
+                OutputFence {
+                    body: 8..9,
+                    closing: 9,
+                    existing: None,
+                    text: None,
+                    prefix: String::new(),
+                },
+            ]
+        );
+        assert_text_block_eq(&doc.lines[5].blocks[0], "stale #=");
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn config_fences_pick_their_language() {
+        let config = crate::config::parse_config(
+            r#"
+[languages.shcalc]
+command = "python3"
+print = 'print("{sentinel}", {expr})'
+fences = ["shc"]
+"#,
+        )
+        .unwrap();
+        let src = "```python\nx #=\n```\n```shc\ny #=\n```\n`shc: 1 + 1 #=`";
+        let doc = MarkdownParser::new()
+            .with_registry(&Registry::new(&config))
+            .parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("x #=", Some("python")),
+                ("y #=", Some("shcalc")),
+                ("1 + 1 #=", Some("shcalc")),
+            ]
+        );
+        // Without the config, the block falls back to the document language.
+        assert_eq!(mk_parser().parse(src).code_blocks()[1], ("y #=", None));
+    }
 }
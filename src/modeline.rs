@@ -0,0 +1,140 @@
+//! Reads settings a file carries in its own lines: a `#!` shebang naming
+//! its interpreter, or an equals modeline in a comment:
+//!
+//! ```text
+//! # equals: language=fend marker=#=
+//! ```
+//!
+//! Like Vim's, modelines that configure the whole file are only looked for
+//! in the first and last few lines. Further in, plain files read them as
+//! directives that switch the language of the lines after them.
+
+/// How many lines at either end of a file may hold a modeline.
+const SCAN_LINES: usize = 5;
+
+/// Line comment tokens of the languages equals evaluates and embeds in.
+const COMMENT_TOKENS: &[&str] = &["#", "//", "--", "%", ";", "/*", "*"];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Modeline {
+    pub language: Option<String>,
+    pub marker: Option<String>,
+    pub precision: Option<usize>,
+}
+
+/// Finds the first modeline near the start or end of `input`. Only blank
+/// lines and comments may come between it and that end of the file, so a
+/// directive following some code doesn't apply to the code before it.
+pub fn find_modeline(input: &str) -> Option<Modeline> {
+    let lines: Vec<&str> = input.lines().collect();
+    let head = lines.iter().take(SCAN_LINES);
+    let tail = lines.iter().rev().take(SCAN_LINES);
+    head.take_while(|line| is_preamble(line))
+        .chain(tail.take_while(|line| is_preamble(line)))
+        .find_map(|line| parse_modeline(line))
+}
+
+fn is_preamble(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || COMMENT_TOKENS.iter().any(|token| line.starts_with(token))
+}
+
+/// Parses a line whose comment text starts with `equals:`, followed by
+/// space-separated `key=value` settings. Unknown keys are ignored.
+pub fn parse_modeline(line: &str) -> Option<Modeline> {
+    let (prefix, settings) = line.trim().split_once("equals:")?;
+    // Only comment punctuation (`#`, `//`, `--`, `%`, `;`, ...) may come first.
+    let prefix = prefix.trim_end();
+    if prefix.is_empty()
+        || prefix
+            .chars()
+            .any(|c| c.is_alphanumeric() || c.is_whitespace())
+    {
+        return None;
+    }
+
+    let mut modeline = Modeline::default();
+    for (key, value) in settings
+        .split_whitespace()
+        .filter_map(|setting| setting.split_once('='))
+    {
+        match key {
+            "language" | "lang" => modeline.language = Some(value.to_ascii_lowercase()),
+            "marker" => modeline.marker = Some(value.to_string()),
+            "precision" => modeline.precision = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some(modeline)
+}
+
+/// The interpreter a `#!` first line runs, e.g. `python3` for
+/// `#!/usr/bin/env python3` or `#!/usr/local/bin/numbat`.
+pub fn shebang_interpreter(input: &str) -> Option<&str> {
+    let mut words = input.lines().next()?.strip_prefix("#!")?.split_whitespace();
+    let program = file_name(words.next()?);
+    if program != "env" {
+        return Some(program);
+    }
+    // `env` may take flags (`-S`) and variables before the program.
+    words
+        .find(|word| !word.starts_with('-') && !word.contains('='))
+        .map(file_name)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modeline_settings() {
+        assert_eq!(
+            parse_modeline("# equals: language=Fend marker=#= precision=3"),
+            Some(Modeline {
+                language: Some("fend".into()),
+                marker: Some("#=".into()),
+                precision: Some(3),
+            })
+        );
+        assert_eq!(
+            parse_modeline("  // equals: lang=numbat").and_then(|m| m.language),
+            Some("numbat".into())
+        );
+        assert_eq!(parse_modeline("x = \"equals: lang=fend\""), None);
+        assert_eq!(parse_modeline("equals: lang=fend"), None);
+    }
+
+    #[test]
+    fn finds_modelines_at_either_end() {
+        let mut lines = ["x = 1"; 12];
+        lines[11] = "# equals: language=numbat";
+        assert!(find_modeline(&lines.join("\n")).is_some());
+        lines[6] = lines[11];
+        lines[11] = "x = 1";
+        assert_eq!(find_modeline(&lines.join("\n")), None);
+        assert!(find_modeline("# equals: lang=fend\n1 + 1 #=").is_some());
+        assert!(find_modeline("#!/bin/fend\n\n// note\n# equals: lang=fend").is_some());
+        assert_eq!(find_modeline("x = 1\n# equals: lang=fend\ny = 2"), None);
+    }
+
+    #[test]
+    fn reads_shebang_interpreters() {
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env python3\nx = 1"),
+            Some("python3")
+        );
+        assert_eq!(
+            shebang_interpreter("#!/usr/local/bin/numbat"),
+            Some("numbat")
+        );
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env -S LANG=C fend -q"),
+            Some("fend")
+        );
+        assert_eq!(shebang_interpreter("# just a comment"), None);
+    }
+}
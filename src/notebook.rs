@@ -0,0 +1,324 @@
+//! Jupyter notebook (`.ipynb`) support.
+//!
+//! Cells are evaluated as one document so later cells see earlier
+//! definitions: code cells line by line in the kernel language, Markdown
+//! cells through `MarkdownParser`. Results are written back by replacing only
+//! the JSON string literals whose text changed, so formatting, cell ids and
+//! outputs stay exactly as they were.
+
+use std::ops::Range;
+
+use crate::{
+    document::{Block, Document, Line, Session},
+    json::{self, Json, JsonString, encode_string},
+    lang::registry::Registry,
+    markdown::MarkdownParser,
+    parser::Parser,
+};
+
+pub struct Notebook<'a> {
+    text: &'a str,
+    /// The kernel language as the notebook names it.
+    language: Option<String>,
+    cells: Vec<Cell>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CellKind {
+    Code,
+    Markdown,
+}
+
+struct Cell {
+    kind: CellKind,
+    /// The `source` string literals, in order.
+    source: Vec<JsonString>,
+    /// `source` was a single string rather than a list of lines.
+    single_string: bool,
+}
+
+impl Cell {
+    fn text(&self) -> String {
+        self.source.iter().map(|s| s.value.as_str()).collect()
+    }
+}
+
+impl<'a> Notebook<'a> {
+    pub fn parse(text: &'a str) -> Result<Self, String> {
+        let root = json::parse(text).map_err(|err| format!("invalid notebook {err}"))?;
+
+        let language = root
+            .get("metadata")
+            .and_then(|meta| {
+                meta.get("kernelspec")
+                    .and_then(|spec| spec.get("language"))
+                    .or_else(|| meta.get("language_info").and_then(|info| info.get("name")))
+            })
+            .and_then(Json::as_str)
+            .map(str::to_string);
+
+        let Some(Json::Array(raw_cells)) = root.get("cells") else {
+            return Err("notebook has no `cells` list".into());
+        };
+
+        let cells = raw_cells
+            .iter()
+            .filter_map(|cell| {
+                let kind = match cell.get("cell_type").and_then(Json::as_str)? {
+                    "code" => CellKind::Code,
+                    "markdown" => CellKind::Markdown,
+                    _ => return None,
+                };
+                let (source, single_string) = match cell.get("source")? {
+                    Json::String(s) => (vec![s.clone()], true),
+                    Json::Array(items) => (
+                        items
+                            .iter()
+                            .filter_map(|item| match item {
+                                Json::String(s) => Some(s.clone()),
+                                _ => None,
+                            })
+                            .collect(),
+                        false,
+                    ),
+                    _ => return None,
+                };
+                Some(Cell {
+                    kind,
+                    source,
+                    single_string,
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            text,
+            language,
+            cells,
+        })
+    }
+
+    /// The kernel language, if `registry` knows it.
+    pub fn language<'r>(&self, registry: &'r Registry) -> Option<&'r str> {
+        registry.for_fence(self.language.as_deref()?)
+    }
+
+    /// Builds one document from every cell, one line per source line.
+    /// Output fences are rejected: their output blocks would add lines, but
+    /// `write_back` maps every line to an existing cell line.
+    pub fn document(&self, registry: &Registry) -> Result<Document, String> {
+        let mut doc = Document {
+            lines: Vec::new(),
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            let text = cell.text();
+            let offset = doc.lines.len();
+            match cell.kind {
+                CellKind::Code => {
+                    for line in text.split('\n') {
+                        doc.lines.push(Line {
+                            blocks: vec![Block::Code(
+                                (0, line.len()),
+                                line.to_string(),
+                                Session::default(),
+                            )],
+                        });
+                    }
+                }
+                CellKind::Markdown => {
+                    let cell_doc = MarkdownParser::new().with_registry(registry).parse(&text);
+                    if !cell_doc.outputs.is_empty() {
+                        return Err(format!(
+                            "cell {}: {{output=true}} fences aren't supported in notebooks; \
+                             use a code cell instead",
+                            index + 1
+                        ));
+                    }
+                    let mut lines = cell_doc.lines;
+                    // `parse` drops the empty line after a trailing newline.
+                    while lines.len() < text.split('\n').count() {
+                        lines.push(Line { blocks: Vec::new() });
+                    }
+                    for mut table in cell_doc.tables {
+                        table.lines = table.lines.start + offset..table.lines.end + offset;
+                        doc.tables.push(table);
+                    }
+                    doc.lines.extend(lines);
+                }
+            }
+        }
+
+        Ok(doc)
+    }
+
+    /// Writes the evaluated `doc` (built by `document`) back into the
+    /// notebook JSON, touching only source strings that changed.
+    pub fn write_back(&self, doc: &Document) -> String {
+        let rendered = doc.render_lines();
+        let mut replacements: Vec<(Range<usize>, String)> = Vec::new();
+        let mut next_line = 0;
+
+        for cell in &self.cells {
+            let line_count = cell.text().split('\n').count();
+            let new_lines = &rendered[next_line..next_line + line_count];
+            next_line += line_count;
+
+            if cell.single_string {
+                let new_text = new_lines.join("\n");
+                if let Some(source) = cell.source.first()
+                    && source.value != new_text
+                {
+                    replacements.push((source.span.clone(), encode_string(&new_text)));
+                }
+                continue;
+            }
+
+            for (source, new_line) in cell.source.iter().zip(new_lines) {
+                let newline = if source.value.ends_with('\n') {
+                    "\n"
+                } else {
+                    ""
+                };
+                let new_value = format!("{new_line}{newline}");
+                if source.value != new_value {
+                    replacements.push((source.span.clone(), encode_string(&new_value)));
+                }
+            }
+        }
+
+        let mut out = String::with_capacity(self.text.len());
+        let mut cursor = 0;
+        for (span, replacement) in replacements {
+            out.push_str(&self.text[cursor..span.start]);
+            out.push_str(&replacement);
+            cursor = span.end;
+        }
+        out.push_str(&self.text[cursor..]);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::CodeBlockUpdate;
+
+    const NOTEBOOK: &str = r##"{
+ "cells": [
+  {
+   "cell_type": "markdown",
+   "id": "intro",
+   "metadata": {},
+   "source": [
+    "# Résumé\n",
+    "Inline `2 + 2 #=` math."
+   ]
+  },
+  {
+   "cell_type": "code",
+   "execution_count": 3,
+   "id": "calc",
+   "metadata": {},
+   "outputs": [{"output_type": "stream", "text": ["4\n"]}],
+   "source": [
+    "x = \"a\\tb\"\n",
+    "len(x) #=\n",
+    "y = 1"
+   ]
+  },
+  {
+   "cell_type": "raw",
+   "source": "len(x) #="
+  }
+ ],
+ "metadata": {
+  "kernelspec": {"display_name": "Python 3", "language": "python", "name": "python3"}
+ },
+ "nbformat": 4,
+ "nbformat_minor": 5
+}
+"##;
+
+    #[test]
+    fn reads_cells_and_kernel_language() {
+        let notebook = Notebook::parse(NOTEBOOK).expect("valid notebook");
+        assert_eq!(notebook.language(&Registry::default()), Some("python"));
+
+        let doc = notebook.document(&Registry::default()).unwrap();
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("2 + 2 #=", None),
+                ("x = \"a\\tb\"", None),
+                ("len(x) #=", None),
+                ("y = 1", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn write_back_only_touches_changed_lines() {
+        let notebook = Notebook::parse(NOTEBOOK).expect("valid notebook");
+        let mut doc = notebook.document(&Registry::default()).unwrap();
+        doc.evaluate_with(|_, blocks| {
+            vec![
+                CodeBlockUpdate {
+                    id: blocks[0].id,
+                    content: "2 + 2 #= 4".into(),
+                },
+                CodeBlockUpdate {
+                    id: blocks[2].id,
+                    content: "len(x) #= \"3\"".into(),
+                },
+            ]
+        });
+
+        let expected = NOTEBOOK
+            .replace("Inline `2 + 2 #=` math.", "Inline `2 + 2 #= 4` math.")
+            .replace("\"len(x) #=\\n\"", "\"len(x) #= \\\"3\\\"\\n\"");
+        assert_eq!(notebook.write_back(&doc), expected);
+    }
+
+    #[test]
+    fn unchanged_notebook_roundtrips_exactly() {
+        let notebook = Notebook::parse(NOTEBOOK).expect("valid notebook");
+        let doc = notebook.document(&Registry::default()).unwrap();
+        assert_eq!(notebook.write_back(&doc), NOTEBOOK);
+    }
+
+    #[test]
+    fn single_string_sources_are_rewritten_whole() {
+        let text = r#"{"cells": [{"cell_type": "code", "source": "a = 2\na * 3 #=", "outputs": []}], "metadata": {}}"#;
+        let notebook = Notebook::parse(text).expect("valid notebook");
+        let mut doc = notebook.document(&Registry::default()).unwrap();
+        doc.evaluate_with(|_, blocks| {
+            vec![CodeBlockUpdate {
+                id: blocks[1].id,
+                content: "a * 3 #= 6".into(),
+            }]
+        });
+
+        assert_eq!(
+            notebook.write_back(&doc),
+            text.replace("a * 3 #=", "a * 3 #= 6")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        assert!(Notebook::parse("{\"cells\": [").is_err());
+        assert!(Notebook::parse("{\"metadata\": {}}").is_err());
+    }
+
+    #[test]
+    fn output_fences_are_rejected() {
+        let text = r#"{"cells": [{"cell_type": "markdown", "source": "```python {output=true}\nprint(1)\n```"}], "metadata": {}}"#;
+        let notebook = Notebook::parse(text).expect("valid notebook");
+        let err = notebook.document(&Registry::default()).unwrap_err();
+        assert!(err.starts_with("cell 1:"), "{err}");
+    }
+}
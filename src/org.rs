@@ -0,0 +1,306 @@
+use crate::{
+    document::{Block, Document, Line, Session},
+    lang::registry::Registry,
+    parser::Parser,
+};
+
+/// Parses Org documents: `#+begin_src` blocks and inline `src_lang{...}`.
+///
+/// Blocks share the document session unless they carry `:session NAME`
+/// (`:session none` gives the block a session of its own). Blocks with
+/// `:eval no` and blocks in languages equals doesn't know stay text.
+pub struct OrgParser {
+    registry: Registry,
+}
+
+/// Header arguments of a source block or inline source call.
+#[derive(Debug, PartialEq)]
+struct HeaderArgs<'a> {
+    session: Option<&'a str>,
+    eval: bool,
+}
+
+impl<'a> HeaderArgs<'a> {
+    fn parse(args: &'a str) -> Self {
+        let mut header = HeaderArgs {
+            session: None,
+            eval: true,
+        };
+        let mut tokens = args.split_whitespace().peekable();
+        while let Some(key) = tokens.next() {
+            let value = tokens.next_if(|t| !t.starts_with(':'));
+            match key.to_ascii_lowercase().as_str() {
+                ":session" => header.session = Some(value.unwrap_or("none")),
+                ":eval" => {
+                    header.eval = !matches!(
+                        value.map(str::to_ascii_lowercase).as_deref(),
+                        Some("no" | "never" | "no-export" | "never-export")
+                    )
+                }
+                _ => {}
+            }
+        }
+        header
+    }
+}
+
+impl OrgParser {
+    pub fn new() -> Self {
+        Self {
+            registry: Registry::default(),
+        }
+    }
+
+    /// Looks up `#+begin_src` and `src_` languages in `registry`.
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    /// Returns the session for a block in `language` with `args`, or `None`
+    /// if the block shouldn't be evaluated.
+    fn session_for(&self, language: &str, args: &str, anonymous: &mut usize) -> Option<Session> {
+        let language = self.registry.for_fence(language)?;
+        let header = HeaderArgs::parse(args);
+        if !header.eval {
+            return None;
+        }
+
+        let session = match header.session {
+            Some("none") => {
+                *anonymous += 1;
+                Session::named(format!("block-{anonymous}"))
+            }
+            Some(name) => Session::named(name),
+            None => Session::default(),
+        };
+        Some(session.with_language(language))
+    }
+
+    /// Recognises `#+begin_src LANG ARGS`, returning the language and args.
+    fn begin_src(line: &str) -> Option<(&str, &str)> {
+        let trimmed = line.trim_start();
+        let rest = strip_prefix_ignore_case(trimmed, "#+begin_src")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let rest = rest.trim();
+        Some(rest.split_once(char::is_whitespace).unwrap_or((rest, "")))
+    }
+
+    fn is_end_src(line: &str) -> bool {
+        strip_prefix_ignore_case(line.trim_start(), "#+end_src").is_some()
+    }
+
+    /// Splits out inline `src_lang{code}` and `src_lang[args]{code}` calls.
+    fn parse_inline_line(&self, line: &str, anonymous: &mut usize) -> Line {
+        let mut blocks = Vec::new();
+        let mut text_start = 0;
+        let mut search_from = 0;
+
+        while let Some(found) = line[search_from..].find("src_") {
+            let start = search_from + found;
+            search_from = start + 4;
+
+            let preceded_by_word = line[..start]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_');
+            if preceded_by_word {
+                continue;
+            }
+            let Some((language, args, code)) = Self::inline_call(line, start + 4) else {
+                continue;
+            };
+            let Some(session) = self.session_for(language, args, anonymous) else {
+                continue;
+            };
+
+            blocks.push(Block::Text(
+                (text_start, code.start),
+                line[text_start..code.start].to_string(),
+            ));
+            blocks.push(Block::Code(
+                (code.start, code.end),
+                line[code.clone()].to_string(),
+                session,
+            ));
+            text_start = code.end;
+            search_from = code.end;
+        }
+
+        if text_start < line.len() || blocks.is_empty() {
+            blocks.push(Block::Text(
+                (text_start, line.len()),
+                line[text_start..].to_string(),
+            ));
+        }
+        Line { blocks }
+    }
+
+    /// Parses `lang[args]{code}` starting at `pos`; returns the language, the
+    /// header args and the byte range of the code between the braces.
+    fn inline_call(line: &str, pos: usize) -> Option<(&str, &str, std::ops::Range<usize>)> {
+        let rest = &line[pos..];
+        let lang_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .unwrap_or(rest.len());
+        if lang_len == 0 {
+            return None;
+        }
+        let language = &rest[..lang_len];
+
+        let mut cursor = pos + lang_len;
+        let mut args = "";
+        if line[cursor..].starts_with('[') {
+            let close = line[cursor..].find(']')?;
+            args = &line[cursor + 1..cursor + close];
+            cursor += close + 1;
+        }
+        if !line[cursor..].starts_with('{') {
+            return None;
+        }
+
+        let code_start = cursor + 1;
+        let mut depth = 1;
+        for (idx, ch) in line[code_start..].char_indices() {
+            match ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((language, args, code_start..code_start + idx));
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+}
+
+impl Parser for OrgParser {
+    fn parse(&self, input: &str) -> Document {
+        let mut lines = Vec::new();
+        // `Some(session)` inside an evaluated block, `Some(None)` inside one
+        // that stays text.
+        let mut block: Option<Option<Session>> = None;
+        let mut anonymous = 0;
+
+        for line in input.lines() {
+            match &block {
+                Some(_) if Self::is_end_src(line) => {
+                    block = None;
+                    lines.push(Line::text(line));
+                }
+                Some(Some(session)) => lines.push(Line {
+                    blocks: vec![Block::Code(
+                        (0, line.len()),
+                        line.to_string(),
+                        session.clone(),
+                    )],
+                }),
+                Some(None) => lines.push(Line::text(line)),
+                None => {
+                    if let Some((language, args)) = Self::begin_src(line) {
+                        block = Some(self.session_for(language, args, &mut anonymous));
+                        lines.push(Line::text(line));
+                    } else {
+                        lines.push(self.parse_inline_line(line, &mut anonymous));
+                    }
+                }
+            }
+        }
+
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn src_block_takes_language_from_header() {
+        let src = "* Notes\n#+BEGIN_SRC numbat\nlet x = 2 m\nx * 3 #=\n#+END_SRC\nafter";
+        let doc = OrgParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("let x = 2 m", Some("numbat")),
+                ("x * 3 #=", Some("numbat"))
+            ]
+        );
+        assert!(matches!(&doc.lines[3].blocks[0], Block::Code(_, _, s) if s.name.is_none()));
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn header_arguments_select_session_or_skip() {
+        let src = "\
+#+begin_src python :session calc
+a = 1
+#+end_src
+#+begin_src python :eval no
+b = 2
+#+end_src
+#+begin_src python :session
+c = 3
+#+end_src
+#+begin_src elisp
+(+ 1 2)
+#+end_src";
+        let doc = OrgParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("a = 1", Some("python")), ("c = 3", Some("python"))]
+        );
+        let session = |idx: usize| match &doc.lines[idx].blocks[0] {
+            Block::Code(_, _, session) => session.name.as_deref(),
+            Block::Text(..) => None,
+        };
+        assert_eq!(session(1), Some("calc"));
+        assert_eq!(session(7), Some("block-1"));
+    }
+
+    #[test]
+    fn inline_src_calls_are_code() {
+        let src = "Speed is src_numbat{30 km/h -> m/s #=} and src_python[:session s]{2 ** 8 #=}, not mysrc_python{1}.";
+        let doc = OrgParser::new().parse(src);
+
+        let blocks = &doc.lines[0].blocks;
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("30 km/h -> m/s #=", Some("numbat")),
+                ("2 ** 8 #=", Some("python"))
+            ]
+        );
+        assert!(
+            matches!(&blocks[3], Block::Code(_, _, s) if *s == Session::named("s").with_language("python"))
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn inline_calls_with_unknown_language_stay_text() {
+        let src = "src_elisp{(+ 1 2)} and src_fend";
+        let doc = OrgParser::new().parse(src);
+        assert!(doc.code_blocks().is_empty());
+        assert_eq!(doc.reconstruct(), src);
+    }
+}
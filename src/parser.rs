@@ -1,13 +1,37 @@
-use crate::document::{Block, Document, Line};
+use crate::document::{Block, Document, Line, Session};
+use crate::lang::{marker_variants, registry::Registry};
+use crate::modeline::parse_modeline;
 
 pub trait Parser {
     fn parse(&self, input: &str) -> Document;
 }
 
-pub struct PlainParser {}
+/// Treats every line as code. A file can mix languages: a modeline
+/// directive (`# equals: lang=numbat`) switches the language of the lines
+/// after it, and a `#<name>=` marker (`#nb=`, `#fd=`) picks the language of
+/// its own line. Each language keeps one session across the file.
+pub struct PlainParser {
+    registry: Registry,
+}
+
+impl PlainParser {
+    pub fn new() -> Self {
+        Self {
+            registry: Registry::default(),
+        }
+    }
+
+    /// Lets directives and marker variants name any language of `registry`.
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+}
 
 impl Parser for PlainParser {
     fn parse(&self, input: &str) -> Document {
+        let mut language: Option<String> = None;
+        let mut errors = Vec::new();
         let lines = input
             .lines()
             .enumerate()
@@ -15,14 +39,44 @@ impl Parser for PlainParser {
                 // Compute column range — the entire line is a code block
                 let start_col = 0;
                 let end_col = line_text.len();
+                // The shebang and modelines are for equals, not the interpreter.
+                let modeline = parse_modeline(line_text);
+                let directive = (i == 0 && line_text.starts_with("#!")) || modeline.is_some();
+                // An unknown name is reported and the language kept.
+                if let Some(name) = modeline.and_then(|modeline| modeline.language) {
+                    match self.registry.for_fence(&name) {
+                        Some(known) => language = Some(known.to_string()),
+                        None => errors.push((i, format!("unknown language `{name}`"))),
+                    }
+                }
+                let block = if directive {
+                    Block::Text((start_col, end_col), line_text.to_string())
+                } else {
+                    let line_language = marker_variants(line_text)
+                        .find_map(|(name, _)| self.registry.for_fence(name))
+                        .map(str::to_string)
+                        .or_else(|| language.clone());
+                    Block::Code(
+                        (start_col, end_col),
+                        line_text.to_string(),
+                        Session {
+                            language: line_language,
+                            ..Session::default()
+                        },
+                    )
+                };
                 Line {
-                    number: i + 1,
-                    blocks: vec![Block::Code((start_col, end_col), line_text.to_string())],
+                    blocks: vec![block],
                 }
             })
             .collect::<Vec<_>>();
 
-        Document { lines }
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors,
+        }
     }
 }
 
@@ -34,7 +88,7 @@ mod tests {
     #[test]
     fn test_plain_parser_roundtrip() {
         let src = "x = 1\ny = 2\n x + y #= 3";
-        let parser = PlainParser {};
+        let parser = PlainParser::new();
 
         let doc = parser.parse(src);
         let reconstructed = doc.reconstruct();
@@ -45,11 +99,61 @@ mod tests {
         for line in &doc.lines {
             assert_eq!(line.blocks.len(), 1);
             match &line.blocks[0] {
-                Block::Code(_, code) => {
+                Block::Code(_, code, _) => {
                     assert!(!code.is_empty());
                 }
                 _ => panic!("expected code block"),
             }
         }
     }
+
+    #[test]
+    fn shebang_and_modelines_are_text() {
+        let src = "#!/usr/bin/env fend\n# equals: marker=#=\n1 + 1 #=";
+        let doc = PlainParser::new().parse(src);
+
+        assert!(matches!(doc.lines[0].blocks[0], Block::Text(..)));
+        assert!(matches!(doc.lines[1].blocks[0], Block::Text(..)));
+        assert!(matches!(doc.lines[2].blocks[0], Block::Code(..)));
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn directives_and_marker_variants_pick_languages() {
+        let src = "1 + 1 #=\n# equals: lang=nbt\nlet x = 2 m\nx -> cm #=\n2 + 2 #fd=\n# equals: lang=python\nx = 1";
+        let doc = PlainParser::new().parse(src);
+        let languages: Vec<Option<&str>> = doc
+            .lines
+            .iter()
+            .map(|line| match &line.blocks[0] {
+                Block::Code(_, _, session) => session.language.as_deref(),
+                Block::Text(..) => Some("text"),
+            })
+            .collect();
+
+        assert_eq!(
+            languages,
+            vec![
+                None,
+                Some("text"),
+                Some("numbat"),
+                Some("numbat"),
+                Some("fend"),
+                Some("text"),
+                Some("python"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_directive_languages_are_reported() {
+        let src = "x = 1\n# equals: lang=pyhton\nx + 1 #=";
+        let doc = PlainParser::new().parse(src);
+
+        assert_eq!(
+            doc.errors,
+            vec![(1, "unknown language `pyhton`".to_string())]
+        );
+        assert_eq!(doc.code_blocks(), vec![("x = 1", None), ("x + 1 #=", None)]);
+    }
 }
@@ -0,0 +1,293 @@
+use crate::{
+    document::{Block, Document, Line, Session},
+    lang::registry::Registry,
+    parser::Parser,
+};
+
+/// Parses reStructuredText documents: `.. code-block:: lang` / `.. code::`
+/// directives, `::` literal blocks and ``` ``inline literals`` ```.
+///
+/// Indented bodies keep their indentation as text, so only the code itself
+/// is evaluated and rewritten. Literal blocks use the language set by the
+/// last `.. highlight:: lang` directive, or the document language; after a
+/// highlight language equals doesn't know, they stay text.
+pub struct RstParser {
+    registry: Registry,
+}
+
+/// An indented body that follows a directive or a `::` paragraph.
+struct Body {
+    /// Indentation of the line that introduced the body.
+    parent_indent: usize,
+    /// Indentation of the first body line, once seen.
+    indent: Option<usize>,
+    /// Directive options (`:linenos:`) may follow until the first blank line.
+    seen_blank: bool,
+    /// `None` when the body is kept as text.
+    session: Option<Session>,
+}
+
+impl RstParser {
+    pub fn new() -> Self {
+        Self {
+            registry: Registry::default(),
+        }
+    }
+
+    /// Resolves `code-block` and `highlight` languages through `registry`.
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    fn indent_of(line: &str) -> usize {
+        line.len() - line.trim_start().len()
+    }
+
+    /// Splits `.. name:: argument` into the directive name and its argument.
+    fn directive(line: &str) -> Option<(&str, &str)> {
+        let rest = line.trim_start().strip_prefix(".. ")?;
+        let (name, argument) = rest.split_once("::")?;
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return None;
+        }
+        Some((name, argument.trim()))
+    }
+
+    /// The session of a body in `language`, or `None` when equals doesn't
+    /// know it. Without a language, the body uses the document's.
+    fn session_for(&self, language: Option<&str>) -> Option<Session> {
+        let Some(name) = language else {
+            return Some(Session::default());
+        };
+        Some(Session::default().with_language(self.registry.for_fence(name)?))
+    }
+
+    fn body_line(line: &str, body: &mut Body) -> Line {
+        if line.trim().is_empty() {
+            body.seen_blank = true;
+            return Line::text(line);
+        }
+        if body.indent.is_none() && !body.seen_blank && line.trim_start().starts_with(':') {
+            return Line::text(line);
+        }
+
+        let indent = *body.indent.get_or_insert(Self::indent_of(line));
+        let Some(session) = &body.session else {
+            return Line::text(line);
+        };
+        let split = indent.min(Self::indent_of(line));
+        let mut blocks = Vec::new();
+        if split > 0 {
+            blocks.push(Block::Text((0, split), line[..split].to_string()));
+        }
+        blocks.push(Block::Code(
+            (split, line.len()),
+            line[split..].to_string(),
+            session.clone(),
+        ));
+        Line { blocks }
+    }
+
+    /// Splits out ``` ``inline literal`` ``` spans.
+    fn parse_inline_line(line: &str) -> Line {
+        let mut blocks = Vec::new();
+        let mut text_start = 0;
+        let mut search_from = 0;
+
+        while let Some(open) = line[search_from..].find("``").map(|p| p + search_from) {
+            let code_start = open + 2;
+            let Some(close) = line[code_start..].find("``").map(|p| p + code_start) else {
+                break;
+            };
+            search_from = close + 2;
+            if close == code_start {
+                continue;
+            }
+
+            blocks.push(Block::Text(
+                (text_start, code_start),
+                line[text_start..code_start].to_string(),
+            ));
+            blocks.push(Block::Code(
+                (code_start, close),
+                line[code_start..close].to_string(),
+                Session::default(),
+            ));
+            text_start = close;
+        }
+
+        if text_start < line.len() || blocks.is_empty() {
+            blocks.push(Block::Text(
+                (text_start, line.len()),
+                line[text_start..].to_string(),
+            ));
+        }
+        Line { blocks }
+    }
+}
+
+impl Parser for RstParser {
+    fn parse(&self, input: &str) -> Document {
+        let mut lines = Vec::new();
+        let mut body: Option<Body> = None;
+        let mut highlight: Option<&str> = None;
+
+        for line in input.lines() {
+            if let Some(current) = body.as_mut() {
+                let ends_body =
+                    !line.trim().is_empty() && Self::indent_of(line) <= current.parent_indent;
+                if !ends_body {
+                    lines.push(Self::body_line(line, current));
+                    continue;
+                }
+                body = None;
+            }
+
+            let parent_indent = Self::indent_of(line);
+            let new_body = |session| {
+                Some(Body {
+                    parent_indent,
+                    indent: None,
+                    seen_blank: false,
+                    session,
+                })
+            };
+
+            match Self::directive(line) {
+                Some(("code-block" | "code" | "sourcecode", argument)) => {
+                    let language = argument.split_whitespace().next();
+                    body = new_body(self.session_for(language.or(highlight)));
+                }
+                Some(("highlight", argument)) => {
+                    highlight = Some(argument);
+                }
+                Some(_) => {
+                    // Other directives (notes, images, ...) have prose bodies.
+                }
+                None if line.trim_end().ends_with("::") => {
+                    body = new_body(self.session_for(highlight));
+                }
+                None => {
+                    lines.push(Self::parse_inline_line(line));
+                    continue;
+                }
+            }
+            lines.push(Line::text(line));
+        }
+
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_block_directive_keeps_indentation() {
+        let src = "\
+Speed
+=====
+
+.. code-block:: numbat
+   :linenos:
+
+   let d = 100 m
+   d / 9.58 s #=
+
+Back to prose.";
+        let doc = RstParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("let d = 100 m", Some("numbat")),
+                ("d / 9.58 s #=", Some("numbat"))
+            ]
+        );
+        match &doc.lines[7].blocks[0] {
+            Block::Text(_, indent) => assert_eq!(indent, "   "),
+            other => panic!("expected indentation text, got {other:?}"),
+        }
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn nested_directive_body_and_unknown_languages() {
+        let src = "\
+.. note::
+
+   .. code:: python
+
+      x = 2
+      x * 21 #=
+
+.. code-block:: bash
+
+   echo hi";
+        let doc = RstParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("x = 2", Some("python")), ("x * 21 #=", Some("python"))]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn literal_blocks_follow_highlight_directive() {
+        let src = "\
+Plain literal::
+
+    2 + 2 #=
+
+.. highlight:: fend
+
+Expanded form:
+
+::
+
+    1 mile to km #=";
+        let doc = RstParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("2 + 2 #=", None), ("1 mile to km #=", Some("fend"))]
+        );
+    }
+
+    #[test]
+    fn unknown_highlight_languages_keep_literal_blocks_as_text() {
+        let src = "\
+.. highlight:: python
+
+::
+
+    2 + 2 #=
+
+.. highlight:: bash
+
+::
+
+    echo $((2 + 2)) #=";
+        let doc = RstParser::new().parse(src);
+
+        assert_eq!(doc.code_blocks(), vec![("2 + 2 #=", Some("python"))]);
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn double_backtick_literals_are_code() {
+        let src = "Use ``2 ** 10 #=`` here, not `emphasis` or ````.";
+        let doc = RstParser::new().parse(src);
+
+        assert_eq!(doc.code_blocks(), vec![("2 ** 10 #=", None)]);
+        assert_eq!(doc.reconstruct(), src);
+    }
+}
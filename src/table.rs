@@ -0,0 +1,348 @@
+//! Pipe table layout, so evaluated cells don't leave a table ragged.
+//!
+//! A column is only re-padded when all of its cells had the same width at
+//! parse time and no longer do after evaluation. Compact tables (`|a|b|`)
+//! are therefore left exactly as they were written.
+
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    /// Indices into `Document::lines`, header row first.
+    pub lines: Range<usize>,
+    pub alignments: Vec<Alignment>,
+    /// Columns whose cells were padded to a common width when parsed.
+    aligned: Vec<bool>,
+}
+
+/// A table row split on its unescaped pipes.
+struct Row<'a> {
+    indent: &'a str,
+    leading_pipe: bool,
+    trailing_pipe: bool,
+    cells: Vec<&'a str>,
+}
+
+impl Table {
+    /// Recognises a table whose header row is `lines[start]`, i.e. a row
+    /// followed by a delimiter row (`| --- | :-: |`) with as many cells.
+    pub fn detect(lines: &[&str], start: usize) -> Option<Table> {
+        let header = split_row(lines.get(start)?)?;
+        let delimiter = split_row(lines.get(start + 1)?)?;
+        if header.cells.len() != delimiter.cells.len() {
+            return None;
+        }
+        let alignments = delimiter
+            .cells
+            .iter()
+            .map(|cell| parse_alignment(cell))
+            .collect::<Option<Vec<_>>>()?;
+
+        let end = (start + 2..lines.len())
+            .find(|&idx| lines[idx].trim().is_empty() || split_row(lines[idx]).is_none())
+            .unwrap_or(lines.len());
+
+        let rows: Vec<Row> = lines[start..end]
+            .iter()
+            .filter_map(|line| split_row(line))
+            .filter(|row| row.cells.len() == alignments.len())
+            .collect();
+        let aligned = (0..alignments.len())
+            .map(|col| column_widths(&rows, col).windows(2).all(|w| w[0] == w[1]))
+            .collect();
+
+        Some(Table {
+            lines: start..end,
+            alignments,
+            aligned,
+        })
+    }
+
+    /// Re-pads the columns of the rendered table that lost their alignment.
+    /// `rendered` holds every line of the document.
+    pub fn realign(&self, rendered: &mut [String]) {
+        let Some(lines) = rendered.get_mut(self.lines.clone()) else {
+            return;
+        };
+
+        let rows: Vec<Option<Row>> = lines
+            .iter()
+            .map(|line| split_row(line).filter(|row| row.cells.len() == self.alignments.len()))
+            .collect();
+
+        let mut columns: Vec<Option<usize>> = vec![None; self.alignments.len()];
+        for (col, width) in columns.iter_mut().enumerate() {
+            let widths = column_widths(rows.iter().flatten(), col);
+            if self.aligned[col] && widths.windows(2).any(|w| w[0] != w[1]) {
+                let content = rows
+                    .iter()
+                    .enumerate()
+                    .filter(|(idx, _)| *idx != 1)
+                    .filter_map(|(_, row)| row.as_ref())
+                    .map(|row| row.cells[col].trim().chars().count())
+                    .max()
+                    .unwrap_or(0);
+                *width = Some(content.max(3));
+            }
+        }
+
+        if columns.iter().all(Option::is_none) {
+            return;
+        }
+
+        let rebuilt: Vec<Option<String>> = rows
+            .iter()
+            .enumerate()
+            .map(|(idx, row)| {
+                row.as_ref()
+                    .map(|row| self.render_row(row, &columns, idx == 1))
+            })
+            .collect();
+        for (line, new_line) in lines.iter_mut().zip(rebuilt) {
+            if let Some(new_line) = new_line {
+                *line = new_line;
+            }
+        }
+    }
+
+    fn render_row(&self, row: &Row, columns: &[Option<usize>], is_delimiter: bool) -> String {
+        let last = row.cells.len() - 1;
+        let cells: Vec<String> = row
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(col, cell)| {
+                let Some(width) = columns[col] else {
+                    return cell.to_string();
+                };
+                let lead = if col == 0 && !row.leading_pipe {
+                    ""
+                } else {
+                    " "
+                };
+                let trail = if col == last && !row.trailing_pipe {
+                    ""
+                } else {
+                    " "
+                };
+                if is_delimiter {
+                    // Delimiter rows keep their own spacing, e.g. `|:----|`.
+                    let segment = lead.len() + width + trail.len();
+                    let lead = if cell.starts_with(' ') { lead } else { "" };
+                    let trail = if cell.ends_with(' ') { trail } else { "" };
+                    let body = segment - lead.len() - trail.len();
+                    format!(
+                        "{lead}{}{trail}",
+                        delimiter_cell(self.alignments[col], body)
+                    )
+                } else {
+                    format!(
+                        "{lead}{}{trail}",
+                        pad_cell(cell.trim(), self.alignments[col], width)
+                    )
+                }
+            })
+            .collect();
+
+        let mut out = String::from(row.indent);
+        if row.leading_pipe {
+            out.push('|');
+        }
+        out.push_str(&cells.join("|"));
+        if row.trailing_pipe {
+            out.push('|');
+        }
+        out
+    }
+}
+
+fn column_widths<'a, 'b: 'a>(
+    rows: impl IntoIterator<Item = &'a Row<'b>>,
+    col: usize,
+) -> Vec<usize> {
+    rows.into_iter()
+        .map(|row| row.cells[col].chars().count())
+        .collect()
+}
+
+/// Splits a row on pipes that are neither escaped nor inside inline code.
+fn split_row(line: &str) -> Option<Row<'_>> {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+    let body = trimmed.trim_end();
+
+    let mut pipes = Vec::new();
+    let mut in_code = false;
+    let mut escaped = false;
+    for (idx, ch) in body.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '`' => in_code = !in_code,
+            '|' if !in_code => pipes.push(idx),
+            _ => {}
+        }
+    }
+    if pipes.is_empty() {
+        return None;
+    }
+
+    let leading_pipe = pipes.first() == Some(&0);
+    let trailing_pipe = body.len() > 1 && pipes.last() == Some(&(body.len() - 1));
+
+    let mut bounds = Vec::new();
+    if !leading_pipe {
+        bounds.push(0);
+    }
+    bounds.extend(pipes.iter().map(|p| p + 1));
+    let mut ends: Vec<usize> = pipes.clone();
+    if !trailing_pipe {
+        ends.push(body.len());
+    }
+    if leading_pipe {
+        ends.remove(0);
+    }
+
+    let cells = bounds
+        .into_iter()
+        .zip(ends)
+        .map(|(start, end)| &body[start..end])
+        .collect();
+
+    Some(Row {
+        indent,
+        leading_pipe,
+        trailing_pipe,
+        cells,
+    })
+}
+
+fn parse_alignment(cell: &str) -> Option<Alignment> {
+    let marker = cell.trim();
+    let dashes = marker.trim_start_matches(':').trim_end_matches(':');
+    if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+        return None;
+    }
+    Some(match (marker.starts_with(':'), marker.ends_with(':')) {
+        (true, true) => Alignment::Center,
+        (true, false) => Alignment::Left,
+        (false, true) => Alignment::Right,
+        (false, false) => Alignment::None,
+    })
+}
+
+fn delimiter_cell(alignment: Alignment, width: usize) -> String {
+    match alignment {
+        Alignment::None => "-".repeat(width),
+        Alignment::Left => format!(":{}", "-".repeat(width - 1)),
+        Alignment::Right => format!("{}:", "-".repeat(width - 1)),
+        Alignment::Center => format!(":{}:", "-".repeat(width - 2)),
+    }
+}
+
+fn pad_cell(content: &str, alignment: Alignment, width: usize) -> String {
+    let gap = width.saturating_sub(content.chars().count());
+    let (left, right) = match alignment {
+        Alignment::None | Alignment::Left => (0, gap),
+        Alignment::Right => (gap, 0),
+        Alignment::Center => (gap / 2, gap - gap / 2),
+    };
+    format!("{}{content}{}", " ".repeat(left), " ".repeat(right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn realigned(src: &str, edit: impl Fn(&mut Vec<String>)) -> String {
+        let lines: Vec<&str> = src.lines().collect();
+        let table = Table::detect(&lines, 0).expect("table");
+        let mut rendered: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+        edit(&mut rendered);
+        table.realign(&mut rendered);
+        rendered.join("\n")
+    }
+
+    #[test]
+    fn detects_table_extent_and_alignment() {
+        let lines = [
+            "| a | b | c | d |",
+            "|---|:--|:-:|--:|",
+            "| 1 | 2 | 3 | 4 |",
+            "",
+            "after",
+        ];
+        let table = Table::detect(&lines, 0).expect("table");
+
+        assert_eq!(table.lines, 0..3);
+        assert_eq!(
+            table.alignments,
+            vec![
+                Alignment::None,
+                Alignment::Left,
+                Alignment::Center,
+                Alignment::Right
+            ]
+        );
+        assert!(Table::detect(&lines, 2).is_none());
+    }
+
+    #[test]
+    fn pipes_inside_inline_code_do_not_split_cells() {
+        let lines = ["| Expr | Value |", "| --- | --- |", "| `a | b` | 1 |"];
+        let table = Table::detect(&lines, 0).expect("table");
+        assert_eq!(table.lines, 0..3);
+        assert_eq!(split_row(lines[2]).unwrap().cells.len(), 2);
+    }
+
+    #[test]
+    fn realigns_grown_column() {
+        let src = "\
+| Item | Cost        |
+|:-----|------------:|
+| Tea  | `2*3 #= 6`  |
+| Cake | `a*b #=`    |";
+        let out = realigned(src, |rows| rows[3] = "| Cake | `a*b #= 1250.5` |".into());
+
+        assert_eq!(
+            out,
+            "\
+| Item |            Cost |
+|:-----|----------------:|
+| Tea  |      `2*3 #= 6` |
+| Cake | `a*b #= 1250.5` |"
+        );
+    }
+
+    #[test]
+    fn realigns_shrunk_centered_column() {
+        let src = "\
+| Name | Result           |
+| ---- | :--------------: |
+| x    | `x #= 123456789` |";
+        let out = realigned(src, |rows| rows[2] = "| x    | `x #= 1` |".into());
+
+        assert_eq!(
+            out,
+            "\
+| Name |  Result  |
+| ---- | :------: |
+| x    | `x #= 1` |"
+        );
+    }
+
+    #[test]
+    fn leaves_compact_tables_alone() {
+        let src = "a|b\n-|-\n`1 #=`|x";
+        let out = realigned(src, |rows| rows[2] = "`1 #= 1`|x".into());
+        assert_eq!(out, "a|b\n-|-\n`1 #= 1`|x");
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{
+    document::{Block, Document},
+    lang::{Language, Markers},
+};
+
+/// Collects the code blocks of `lang` into a standalone script, rewriting
+/// marked lines according to `markers`. With `session`, only blocks of that
+/// named session are kept. Separate blocks are divided by a blank line.
+pub fn tangle(
+    doc: &Document,
+    lang: &dyn Language,
+    session: Option<&str>,
+    markers: Markers,
+) -> String {
+    let mut out = Vec::new();
+    let mut last_line: Option<usize> = None;
+
+    for (idx, line) in doc.lines.iter().enumerate() {
+        for block in &line.blocks {
+            let Block::Code(_, code, block_session) = block else {
+                continue;
+            };
+            if block_session.language.as_deref() != Some(lang.name())
+                || session.is_some_and(|name| block_session.name.as_deref() != Some(name))
+            {
+                continue;
+            }
+
+            if last_line.is_some_and(|last| last + 1 < idx) {
+                out.push(String::new());
+            }
+            out.push(lang.tangle_line(code, markers));
+            last_line = Some(idx);
+        }
+    }
+
+    if out.is_empty() {
+        return String::new();
+    }
+    out.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        frontmatter::parse_front_matter, lang::python::PythonLang, markdown::MarkdownParser,
+        parser::Parser,
+    };
+
+    const DOC: &str = "\
+# Notes
+
+Inline `1 + 1 #=` is prose.
+
+```python
+x = 2
+x * 3 #= 6
+```
+
+```numbat
+let y = 3 m
+```
+
+```{python, eval=FALSE}
+launch_rockets()
+```
+
+```python
+def f(n):
+    n + 1 #= 3
+```";
+
+    fn parse(src: &str) -> Document {
+        let mut doc = MarkdownParser::fenced_only()
+            .with_front_matter(parse_front_matter(src).as_ref())
+            .parse(src);
+        doc.set_default_language("python");
+        doc
+    }
+
+    #[test]
+    fn collects_blocks_of_one_language() {
+        let script = tangle(&parse(DOC), &PythonLang::default(), None, Markers::Strip);
+        assert_eq!(script, "x = 2\nx * 3\n\ndef f(n):\n    n + 1\n");
+    }
+
+    #[test]
+    fn markers_become_asserts() {
+        let script = tangle(&parse(DOC), &PythonLang::default(), None, Markers::Assert);
+        assert_eq!(
+            script,
+            "x = 2\nassert str(x * 3) == '6'\n\ndef f(n):\n    assert str(n + 1) == '3'\n"
+        );
+    }
+
+    #[test]
+    fn session_filter_keeps_named_blocks() {
+        let src = "---\nequals: { sessions: isolated }\n---\n```\na = 1\n```\n```\nb = 2\n```";
+        let script = tangle(
+            &parse(src),
+            &PythonLang::default(),
+            Some("block-2"),
+            Markers::Strip,
+        );
+        assert_eq!(script, "b = 2\n");
+    }
+}
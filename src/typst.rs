@@ -0,0 +1,291 @@
+use crate::{
+    document::{Block, Document, Line, Session},
+    lang::{fend, numbat, registry::Registry},
+    parser::Parser,
+};
+
+/// Parses Typst documents: ```` ```lang ```` raw blocks and inline
+/// `` `raw` `` text.
+///
+/// Raw blocks tagged with a language equals doesn't know stay text. A
+/// `// equals: let=numbat` comment opts the document into evaluating
+/// `#let name = expr` calculations in Numbat or Fend, until a `let=off`
+/// comment; in Fend the `let` keyword is kept as text since Fend assigns
+/// with a bare `name = expr`.
+pub struct TypstParser {
+    registry: Registry,
+}
+
+/// An open raw block.
+struct RawBlock {
+    /// The backticks that close it.
+    fence: String,
+    /// `None` when the block stays text.
+    session: Option<Session>,
+}
+
+impl TypstParser {
+    pub fn new() -> Self {
+        Self {
+            registry: Registry::default(),
+        }
+    }
+
+    /// Matches raw block tags and `let=` directives against `registry`.
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    /// Recognises an opening raw block fence, returning its backticks and
+    /// the block's session.
+    fn open_raw(&self, line: &str) -> Option<RawBlock> {
+        let trimmed = line.trim_start();
+        let ticks = trimmed.len() - trimmed.trim_start_matches('`').len();
+        if ticks < 3 {
+            return None;
+        }
+        let fence = &trimmed[..ticks];
+        let rest = &trimmed[ticks..];
+        if rest.contains(fence) {
+            // Opened and closed on one line.
+            return None;
+        }
+
+        let session = match rest.split_whitespace().next() {
+            Some(tag) => self
+                .registry
+                .for_fence(tag)
+                .map(|l| Session::default().with_language(l)),
+            None => Some(Session::default()),
+        };
+        Some(RawBlock {
+            fence: fence.to_string(),
+            session,
+        })
+    }
+
+    /// Reads `// equals: let=numbat`, returning the language for `#let`,
+    /// or `Some(None)` for `let=off`. Other languages are ignored, since
+    /// only Numbat and Fend bindings read like Typst's.
+    fn let_directive(&self, line: &str) -> Option<Option<&'static str>> {
+        let rest = line.trim().strip_prefix("//")?.trim_start();
+        let options = rest.strip_prefix("equals:")?;
+        options.split_whitespace().find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            if key != "let" {
+                return None;
+            }
+            if value == "off" {
+                return Some(None);
+            }
+            let language = self.registry.for_fence(value)?;
+            [numbat::INFO.name, fend::INFO.name]
+                .into_iter()
+                .find(|name| *name == language)
+                .map(Some)
+        })
+    }
+
+    /// Splits `#let name = expr` into text and code. Numbat keeps the `let`
+    /// keyword in the code; other languages get `name = expr`.
+    fn parse_let_line(line: &str, language: &str) -> Option<Line> {
+        let indent = line.len() - line.trim_start().len();
+        let after_hash = line[indent..].strip_prefix("#let ")?;
+        let (name, _) = after_hash.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            return None;
+        }
+
+        let code_start = if language == numbat::INFO.name {
+            indent + 1
+        } else {
+            line.len() - after_hash.trim_start().len()
+        };
+        let session = Session::default().with_language(language);
+        Some(Line {
+            blocks: vec![
+                Block::Text((0, code_start), line[..code_start].to_string()),
+                Block::Code(
+                    (code_start, line.len()),
+                    line[code_start..].to_string(),
+                    session,
+                ),
+            ],
+        })
+    }
+
+    /// Splits out `` `inline raw` `` spans.
+    fn parse_inline_line(line: &str) -> Line {
+        let mut blocks = Vec::new();
+        let mut text_start = 0;
+        let mut search_from = 0;
+
+        while let Some(open) = line[search_from..].find('`').map(|p| p + search_from) {
+            let Some(close) = line[open + 1..].find('`').map(|p| p + open + 1) else {
+                break;
+            };
+            search_from = close + 1;
+            if close == open + 1 {
+                continue;
+            }
+
+            blocks.push(Block::Text(
+                (text_start, open + 1),
+                line[text_start..open + 1].to_string(),
+            ));
+            blocks.push(Block::Code(
+                (open + 1, close),
+                line[open + 1..close].to_string(),
+                Session::default(),
+            ));
+            text_start = close;
+        }
+
+        if text_start < line.len() || blocks.is_empty() {
+            blocks.push(Block::Text(
+                (text_start, line.len()),
+                line[text_start..].to_string(),
+            ));
+        }
+        Line { blocks }
+    }
+}
+
+impl Parser for TypstParser {
+    fn parse(&self, input: &str) -> Document {
+        let mut lines = Vec::new();
+        let mut raw: Option<RawBlock> = None;
+        let mut let_language = None;
+
+        for line in input.lines() {
+            if let Some(block) = &raw {
+                if line.trim_start().starts_with(block.fence.as_str()) {
+                    raw = None;
+                    lines.push(Line::text(line));
+                } else if let Some(session) = &block.session {
+                    lines.push(Line {
+                        blocks: vec![Block::Code(
+                            (0, line.len()),
+                            line.to_string(),
+                            session.clone(),
+                        )],
+                    });
+                } else {
+                    lines.push(Line::text(line));
+                }
+                continue;
+            }
+
+            if let Some(block) = self.open_raw(line) {
+                raw = Some(block);
+                lines.push(Line::text(line));
+            } else if line.trim_start().starts_with("//") {
+                if let Some(language) = self.let_directive(line) {
+                    let_language = language;
+                }
+                lines.push(Line::text(line));
+            } else if let Some(line) =
+                let_language.and_then(|language| Self::parse_let_line(line, language))
+            {
+                lines.push(line);
+            } else {
+                lines.push(Self::parse_inline_line(line));
+            }
+        }
+
+        Document {
+            lines,
+            tables: Vec::new(),
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_blocks_and_inline_raw_are_code() {
+        let src = "\
+= Report
+
+```python
+x = 21
+x * 2 #=
+```
+
+````rust
+let s = \"```\";
+````
+
+Inline `2 ** 10 #=` value, and a #let y = 3 * 4 #= left alone.";
+        let doc = TypstParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("x = 21", Some("python")),
+                ("x * 2 #=", Some("python")),
+                ("2 ** 10 #=", None),
+            ]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn let_directive_evaluates_let_bindings() {
+        let src = "\
+// equals: let=numbat
+#let width = 3 m
+  #let area = width * 2 m #=
+#let f(x) = x + 1";
+        let doc = TypstParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![
+                ("let width = 3 m", Some("numbat")),
+                ("let area = width * 2 m #=", Some("numbat")),
+            ]
+        );
+        assert_eq!(doc.reconstruct(), src);
+    }
+
+    #[test]
+    fn fend_let_bindings_drop_the_keyword() {
+        let src = "// equals: let=fend\n#let total = 5 GB to MB #=";
+        let doc = TypstParser::new().parse(src);
+
+        assert_eq!(
+            doc.code_blocks(),
+            vec![("total = 5 GB to MB #=", Some("fend"))]
+        );
+        match &doc.lines[1].blocks[0] {
+            Block::Text(_, prefix) => assert_eq!(prefix, "#let "),
+            other => panic!("expected `#let ` text, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn let_evaluation_is_limited_to_numbat_and_fend_and_can_be_switched_off() {
+        let src = "\
+// equals: let=python
+#let a = 1 #=
+// equals: let=nbt
+#let b = 2 m #=
+// equals: let=off
+#let c = 3 #=";
+        let doc = TypstParser::new().parse(src);
+
+        assert_eq!(doc.code_blocks(), vec![("let b = 2 m #=", Some("numbat"))]);
+        assert_eq!(doc.reconstruct(), src);
+    }
+}